@@ -1 +1,2 @@
 #include "esp_camera.h"
+#include "img_converters.h"
@@ -1,10 +1,12 @@
+use std::sync::Arc;
+
 use crate::Camera;
 use anyhow::Result;
 use bstr::ByteSlice;
 use esp32_nimble::{uuid128, BLEClient, BLEDevice, BLEError, BLEScan};
-use esp_idf_sys::camera;
-use espcam::espcam::FrameBuffer;
-use image::{ImageBuffer, ImageFormat, Rgb};
+use espcam::espcam::{Overflow, StreamOptions};
+use futures::StreamExt;
+use image::ImageFormat;
 use log::{error, info};
 
 pub struct IDMBle<'a> {
@@ -70,7 +72,7 @@ impl<'a> IDMBle<'a> {
     }
 }
 
-pub async fn idotmatrix_stream_task(camera: Camera<'_>) -> Result<()> {
+pub async fn idotmatrix_stream_task(camera: Arc<Camera<'static>>) -> Result<()> {
     let ble_device = BLEDevice::take();
     let mut ble_client = ble_device.new_client();
 
@@ -81,14 +83,26 @@ pub async fn idotmatrix_stream_task(camera: Camera<'_>) -> Result<()> {
         .await
         .unwrap();
 
+    // the BLE upload is slower than the sensor, only ever show the latest frame
+    let mut frames = camera.frames(
+        StreamOptions::default()
+            .with_fps(5.0)
+            .with_overflow(Overflow::DropOldest),
+    )?;
+
     loop {
-        camera.get_framebuffer();
-        let framebuffer = camera.get_framebuffer();
+        let framebuffer = frames.next().await;
 
         if let Some(framebuffer) = framebuffer {
             info!("Creating image");
 
-            let img = framebuffer_to_img(framebuffer);
+            let img = match espcam::convert::to_rgb(&framebuffer) {
+                Ok(img) => img,
+                Err(err) => {
+                    error!("frame conversion failed: {}", err);
+                    continue;
+                }
+            };
 
             info!("Resizing image");
             let scaled =
@@ -115,32 +129,3 @@ pub async fn idotmatrix_stream_task(camera: Camera<'_>) -> Result<()> {
 
     //ble::ble_advertise_task(name, ble_server, ble_advertising).await;
 }
-
-fn framebuffer_to_img(framebuffer: FrameBuffer<'_>) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let data = framebuffer.data();
-    ImageBuffer::from_fn(
-        framebuffer.width() as u32,
-        framebuffer.height() as u32,
-        |x, y| match framebuffer.format() {
-            camera::pixformat_t_PIXFORMAT_RGB565 => {
-                let pix_addr = (x + y * framebuffer.width() as u32) as usize * 2;
-                let raw_pixel = u16::from_be_bytes([data[pix_addr], data[pix_addr + 1]]);
-
-                let decoded = rgb565::Rgb565::unpack_565(raw_pixel);
-
-                Rgb([decoded.0, decoded.1, decoded.2])
-            }
-
-            camera::pixformat_t_PIXFORMAT_GRAYSCALE => {
-                let pix_addr = (x + y * framebuffer.width() as u32) as usize;
-                let raw_pixel = data[pix_addr];
-
-                Rgb([raw_pixel, raw_pixel, raw_pixel])
-            }
-
-            _ => {
-                panic!("unsupported format");
-            }
-        },
-    )
-}
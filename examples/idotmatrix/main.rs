@@ -1,9 +1,14 @@
 use core::panic;
 
+use std::sync::Arc;
+
 use anyhow::Result;
 
-use esp_idf_svc::hal::peripherals::Peripherals;
-use espcam::espcam::Camera;
+use esp_idf_svc::{hal::peripherals::Peripherals, nvs::EspDefaultNvsPartition};
+use espcam::{
+    espcam::{Camera, CameraConfig, FrameSize, PixelFormat, PrivacyMask},
+    store::Store,
+};
 use log::error;
 
 use crate::idotmatrixble::idotmatrix_stream_task;
@@ -16,26 +21,19 @@ fn main() -> Result<()> {
 
     let peripherals = Peripherals::take().unwrap();
 
-    let camera = Camera::new(
-        peripherals.pins.gpio32,
-        peripherals.pins.gpio0,
-        peripherals.pins.gpio5,
-        peripherals.pins.gpio18,
-        peripherals.pins.gpio19,
-        peripherals.pins.gpio21,
-        peripherals.pins.gpio36,
-        peripherals.pins.gpio39,
-        peripherals.pins.gpio34,
-        peripherals.pins.gpio35,
-        peripherals.pins.gpio25,
-        peripherals.pins.gpio23,
-        peripherals.pins.gpio22,
-        peripherals.pins.gpio26,
-        peripherals.pins.gpio27,
-        esp_idf_sys::camera::pixformat_t_PIXFORMAT_RGB565,
-        esp_idf_sys::camera::framesize_t_FRAMESIZE_240X240,
-    )
-    .unwrap();
+    let camera = Arc::new(
+        Camera::new(
+            espcam::camera_pins!(AiThinker, peripherals.pins),
+            CameraConfig::new(PixelFormat::Rgb565, FrameSize::R240x240),
+        )
+        .unwrap(),
+    );
+
+    // the mask the telegram bot example keeps, no frame leaves unmasked
+    let store = Store::new(EspDefaultNvsPartition::take()?, "espcam")?;
+    if let Some(mask) = store.load::<PrivacyMask>("privacy")? {
+        camera.set_privacy_mask(mask)?;
+    }
 
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -1,14 +1,26 @@
-use anyhow::Result;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
 
 use bot_api::{telegram_post_multipart, Esp32Api};
-use esp_idf_hal::{gpio::PinDriver, io::Write};
+use esp_idf_hal::io::Write;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::peripherals::Peripherals,
     http::{server::EspHttpServer, Method},
+    nvs::EspDefaultNvsPartition,
 };
 use esp_idf_sys::esp_restart;
-use espcam::{config::get_config, espcam::Camera, wifi_handler::my_wifi};
+use espcam::{
+    config::get_config,
+    espcam::{Camera, CameraConfig, CameraService, FrameSize, PixelFormat, PrivacyMask},
+    flash::{BlinkPattern, Flash, FlashConfig},
+    motion::{MotionConfig, MotionDetector, MotionMonitor},
+    overlay::Overlay,
+    store::Store,
+    timelapse::{Capture, CapturePlan, SinkError, TimeLapse, TimeLapseTask, TimeOfDay},
+    wifi_handler::my_wifi,
+};
 use frankenstein::{
     methods::{GetUpdatesParams, SendChatActionParams, SendMessageParams},
     TelegramApi,
@@ -27,6 +39,9 @@ const DEFAULT_CONFIG: BotConfiguration = BotConfiguration {
     public_use: true,
 };
 
+// keeps a busy scene from flooding the chat
+const MOTION_ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
 struct BotState {
     config: BotConfiguration,
     owner_id: i64,
@@ -41,8 +56,14 @@ fn main() -> Result<()> {
 
     let peripherals = Peripherals::take().unwrap();
 
-    let mut flash_led = PinDriver::output(peripherals.pins.gpio4).unwrap();
-    flash_led.set_low().unwrap();
+    let camera_config = CameraConfig::new(PixelFormat::Jpeg, FrameSize::Uxga).with_jpeg_retries(2);
+
+    let mut camera_pins = espcam::camera_pins!(AiThinker, peripherals.pins);
+    let flash_led = camera_pins
+        .flash_led
+        .take()
+        .expect("the board has no flash LED");
+    let mut flash = Flash::new(flash_led, FlashConfig::default(), &camera_config).unwrap();
 
     let config = get_config();
 
@@ -56,56 +77,106 @@ fn main() -> Result<()> {
         Err(err) => {
             error!("Could not connect to Wi-Fi network: {:?}", err);
 
-            for _ in 0..5 {
-                flash_led.set_high().unwrap();
-                std::thread::sleep(std::time::Duration::from_millis(1));
-                flash_led.set_low().unwrap();
-                std::thread::sleep(std::time::Duration::from_millis(80));
-            }
+            flash.blink(&BlinkPattern::ERROR).ok();
 
             unsafe { esp_restart() };
         }
     };
 
-    flash_led.set_high().unwrap();
-    std::thread::sleep(std::time::Duration::from_millis(1));
-    flash_led.set_low().unwrap();
-
-    let mut camera = Camera::new(
-        peripherals.pins.gpio32,
-        peripherals.pins.gpio0,
-        peripherals.pins.gpio5,
-        peripherals.pins.gpio18,
-        peripherals.pins.gpio19,
-        peripherals.pins.gpio21,
-        peripherals.pins.gpio36,
-        peripherals.pins.gpio39,
-        peripherals.pins.gpio34,
-        peripherals.pins.gpio35,
-        peripherals.pins.gpio25,
-        peripherals.pins.gpio23,
-        peripherals.pins.gpio22,
-        peripherals.pins.gpio26,
-        peripherals.pins.gpio27,
-        esp_idf_sys::camera::pixformat_t_PIXFORMAT_JPEG,
-        esp_idf_sys::camera::framesize_t_FRAMESIZE_UXGA,
+    flash.blink(&BlinkPattern::READY).ok();
+
+    let camera = Camera::new(
+        camera_pins,
+        camera_config.with_settle_time(Duration::from_millis(200)),
     )
     .unwrap();
 
-    camera.apply_low_noise_profile();
+    // masks set with /mask survive reboots
+    let nvs = EspDefaultNvsPartition::take()?;
+    let mut store = Store::new(nvs.clone(), "espcam")?;
+    match store.load::<PrivacyMask>("privacy") {
+        Ok(Some(mask)) => {
+            if let Err(err) = camera.set_privacy_mask(mask) {
+                error!("could not apply the privacy mask: {}", err);
+            }
+        }
+        Ok(None) => {}
+        Err(err) => error!("could not load the privacy mask: {}", err),
+    }
+
+    let report = camera.apply_low_noise_profile();
+    for (setting, err) in report.failed() {
+        error!("could not apply {:?}: {}", setting, err);
+    }
 
-    let camera = std::sync::Arc::new(camera);
+    // the HTTP handler and the bot loop capture from different threads
+    let camera = std::sync::Arc::new(CameraService::start(camera)?);
+
+    // photos of whatever moves go to the owner
+    let monitor = MotionMonitor::start(&camera, MotionDetector::new(MotionConfig::default()))?;
+    let motion_events = monitor.subscribe(1);
+    let alert_camera = camera.clone();
+    let (owner_id, bot_token) = (config.bot_owner_id, config.bot_token);
+    std::thread::Builder::new()
+        .name("motion-alerts".into())
+        .stack_size(16 * 1024)
+        .spawn(move || {
+            let mut last_alert: Option<Instant> = None;
+            for event in motion_events {
+                if last_alert.is_some_and(|last| last.elapsed() < MOTION_ALERT_INTERVAL) {
+                    continue;
+                }
+                let Ok(frame) = alert_camera.snapshot("motion-alert") else {
+                    continue;
+                };
+                last_alert = Some(Instant::now());
+
+                let caption = if event.zones.is_empty() {
+                    "Motion detected".to_string()
+                } else {
+                    format!("Motion in {}", event.zones.join(", "))
+                };
+                if let Err(err) = telegram_post_multipart(
+                    format!("https://api.telegram.org/bot{}/sendPhoto", bot_token),
+                    frame.data(),
+                    owner_id,
+                    Some(caption),
+                    None,
+                ) {
+                    error!("could not send the motion alert: {:?}", err);
+                }
+            }
+        })?;
+
+    // plans set with /timelapse survive reboots, their frames go to the owner
+    let timelapse = TimeLapse::recover(Store::new(nvs, "timelapse")?)?.start(
+        camera.clone(),
+        move |capture: &Capture<'_>| {
+            telegram_post_multipart(
+                format!("https://api.telegram.org/bot{}/sendPhoto", bot_token),
+                &capture.jpeg()?,
+                owner_id,
+                Some(format!(
+                    "{} #{}, {}",
+                    capture.plan.name(),
+                    capture.index,
+                    capture.time
+                )),
+                None,
+            )
+            .map(|_| ())
+            .map_err(|err| SinkError::Other(format!("{:?}", err)))
+        },
+    )?;
 
     let mut server = EspHttpServer::new(&esp_idf_svc::http::server::Configuration::default())?;
 
     let camera2 = camera.clone();
 
     server.fn_handler("/camera.jpg", Method::Get, move |request| {
-        camera2.get_framebuffer();
-        // take two frames to get a fresh one
-        let framebuffer = camera2.get_framebuffer();
+        let framebuffer = camera2.snapshot("http");
 
-        if let Some(framebuffer) = framebuffer {
+        if let Ok(framebuffer) = framebuffer {
             let data = framebuffer.data();
 
             let headers = [
@@ -122,6 +193,8 @@ fn main() -> Result<()> {
         Ok::<(), esp_idf_hal::io::EspIOError>(())
     })?;
 
+    let overlay = Overlay::cctv("espcam");
+
     let mut bot_state = BotState {
         config: DEFAULT_CONFIG,
         owner_id: config.bot_owner_id,
@@ -196,17 +269,31 @@ fn main() -> Result<()> {
                         )
                         .ok();
 
-                        if bot_state.config.should_use_flash {
-                            flash_led.set_high().unwrap();
+                        let frame = if bot_state.config.should_use_flash {
+                            // the pre-flash lets auto exposure adjust to the light
+                            flash
+                                .with_flash(|| camera.snapshot("telegram"))
+                                .unwrap_or_else(|err| {
+                                    error!("flash error: {}", err);
+                                    camera.snapshot("telegram")
+                                })
+                        } else {
+                            camera.snapshot("telegram")
                         }
+                        .ok();
 
-                        camera.get_framebuffer();
-                        let framebuffer = camera.get_framebuffer();
-
-                        flash_led.set_low().unwrap();
+                        if let Some(framebuffer) = frame {
+                            // send the plain frame if there's no memory to stamp it
+                            let stamped = overlay.apply(&*framebuffer, framebuffer.meta());
+                            let data = match &stamped {
+                                Ok(stamped) => stamped.data(),
+                                Err(err) => {
+                                    error!("could not stamp the photo: {}", err);
+                                    framebuffer.data()
+                                }
+                            };
 
-                        if let Some(framebuffer) = framebuffer {
-                            let file_size = framebuffer.data().len();
+                            let file_size = data.len();
                             let file_size_kb = file_size as f64 / 1024.0;
 
                             let caption = format!("File size: {:.2} KB", file_size_kb);
@@ -216,7 +303,7 @@ fn main() -> Result<()> {
                                     "https://api.telegram.org/bot{}/sendPhoto",
                                     bot_state.bot_token
                                 ),
-                                framebuffer.data(),
+                                data,
                                 message.chat.id,
                                 Some(caption),
                                 Some(message.message_id),
@@ -321,9 +408,124 @@ fn main() -> Result<()> {
                             send_owner_info(&bot_state);
                         }
                     }
+                    command if command.starts_with("/mask") => {
+                        if message.chat.id != bot_state.owner_id {
+                            continue;
+                        }
+
+                        let reply = update_mask(&camera, &mut store, command)
+                            .unwrap_or_else(|err| format!("Mask not changed: {}", err));
+
+                        api.send_message(
+                            &SendMessageParams::builder()
+                                .chat_id(message.chat.id)
+                                .text(reply)
+                                .build(),
+                        )
+                        .ok();
+                    }
+                    command if command.starts_with("/timelapse") => {
+                        if message.chat.id != bot_state.owner_id {
+                            continue;
+                        }
+
+                        let reply = update_timelapse(&timelapse, command)
+                            .unwrap_or_else(|err| format!("Time-lapse not changed: {}", err));
+
+                        api.send_message(
+                            &SendMessageParams::builder()
+                                .chat_id(message.chat.id)
+                                .text(reply)
+                                .build(),
+                        )
+                        .ok();
+                    }
                     _ => {}
                 }
             }
         }
     }
 }
+
+/// `/mask x y width height` in percent of the frame adds a rectangle,
+/// `/mask clear` removes them all
+fn update_mask(camera: &CameraService, store: &mut Store, command: &str) -> Result<String> {
+    let mut mask = camera.configure(|camera| camera.privacy_mask())?;
+
+    let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        ["clear"] => mask.clear(),
+        [x, y, width, height] => {
+            let [x, y, width, height] =
+                [x, y, width, height].map(|percent| percent.parse::<f32>().map(|p| p / 100.0));
+            mask = mask.rect(x?, y?, width?, height?);
+        }
+        _ => bail!("use /mask x y width height, in percent, or /mask clear"),
+    }
+
+    // only keep masks the camera took
+    let regions = mask.regions().len();
+    let applied = mask.clone();
+    camera.configure(move |camera| camera.set_privacy_mask(applied))??;
+    store.save("privacy", &mask)?;
+
+    Ok(format!("{} masked regions", regions))
+}
+
+/// `/timelapse` lists the plans, `/timelapse name minutes [HH:MM-HH:MM]` adds
+/// or replaces one, `/timelapse remove name` deletes it
+fn update_timelapse(timelapse: &TimeLapseTask, command: &str) -> Result<String> {
+    let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+    match args.as_slice() {
+        [] => {
+            let plans: Vec<String> = timelapse
+                .plans()
+                .iter()
+                .map(|plan| {
+                    let captures = timelapse
+                        .progress(plan.name())
+                        .map_or(0, |progress| progress.captures());
+                    format!(
+                        "{}: every {} min, {} frames",
+                        plan.name(),
+                        plan.interval().as_secs() / 60,
+                        captures
+                    )
+                })
+                .collect();
+            if plans.is_empty() {
+                Ok("No time-lapse plans".to_string())
+            } else {
+                Ok(plans.join("\n"))
+            }
+        }
+        ["remove", name] => match timelapse.remove_plan(name)? {
+            true => Ok(format!("Removed {}", name)),
+            false => bail!("no plan named {}", name),
+        },
+        [name, minutes, window @ ..] => {
+            let minutes: u64 = minutes.parse()?;
+            if minutes == 0 {
+                bail!("the interval must be at least one minute");
+            }
+            let Some(secs) = minutes.checked_mul(60) else {
+                bail!("{} minutes is too long an interval", minutes);
+            };
+            let mut plan = CapturePlan::new(*name, Duration::from_secs(secs));
+            match window {
+                [] => {}
+                [window] => {
+                    let Some((start, end)) = window.split_once('-') else {
+                        bail!("use HH:MM-HH:MM for the window");
+                    };
+                    plan = plan.with_window(start.parse::<TimeOfDay>()?, end.parse::<TimeOfDay>()?);
+                }
+                _ => bail!("use /timelapse name minutes [HH:MM-HH:MM]"),
+            }
+
+            timelapse.set_plan(plan)?;
+            Ok(format!("{} captures every {} min", name, minutes))
+        }
+        _ => bail!("use /timelapse name minutes [HH:MM-HH:MM] or /timelapse remove name"),
+    }
+}
@@ -5,8 +5,16 @@ use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::peripherals::Peripherals,
     http::{server::EspHttpServer, Method},
+    nvs::EspDefaultNvsPartition,
+};
+use espcam::{
+    config::get_config,
+    espcam::{Camera, CameraConfig, FbLocation, FrameSize, PixelFormat, PrivacyMask},
+    exif::{frame_with_exif, ExifData},
+    overlay::Overlay,
+    store::Store,
+    wifi_handler::my_wifi,
 };
-use espcam::{config::get_config, espcam::Camera, wifi_handler::my_wifi};
 
 fn main() -> Result<()> {
     esp_idf_svc::sys::link_patches();
@@ -30,36 +38,63 @@ fn main() -> Result<()> {
         }
     };
 
-    let camera = Camera::new(
-        peripherals.pins.gpio32,
-        peripherals.pins.gpio0,
-        peripherals.pins.gpio5,
-        peripherals.pins.gpio18,
-        peripherals.pins.gpio19,
-        peripherals.pins.gpio21,
-        peripherals.pins.gpio36,
-        peripherals.pins.gpio39,
-        peripherals.pins.gpio34,
-        peripherals.pins.gpio35,
-        peripherals.pins.gpio25,
-        peripherals.pins.gpio23,
-        peripherals.pins.gpio22,
-        peripherals.pins.gpio26,
-        peripherals.pins.gpio27,
-        esp_idf_sys::camera::pixformat_t_PIXFORMAT_JPEG,
-        esp_idf_sys::camera::framesize_t_FRAMESIZE_UXGA,
-    )
-    .unwrap();
+    let configs = [
+        CameraConfig::new(PixelFormat::Jpeg, FrameSize::Uxga),
+        CameraConfig::new(PixelFormat::Jpeg, FrameSize::Svga),
+        CameraConfig::new(PixelFormat::Jpeg, FrameSize::Qvga).with_fb_location(FbLocation::Dram),
+    ];
+    let mut camera = match Camera::with_fallback(
+        espcam::camera_pins!(AiThinker, peripherals.pins),
+        configs.clone(),
+    ) {
+        Ok(camera) => camera,
+        Err(err) => bail!("Could not start the camera: {}", err),
+    };
+
+    // the mask the telegram bot example keeps, no frame leaves unmasked: go
+    // down the fallback configs until one has the memory to mask its frames
+    let store = Store::new(EspDefaultNvsPartition::take()?, "espcam")?;
+    if let Some(mask) = store.load::<PrivacyMask>("privacy")? {
+        let started = camera.config().clone();
+        let mut smaller = configs
+            .into_iter()
+            .skip_while(|config| *config != started)
+            .skip(1);
+        while let Err(err) = camera.set_privacy_mask(mask.clone()) {
+            let Some(config) = smaller.next() else {
+                bail!("Could not apply the privacy mask: {}", err);
+            };
+            log::warn!("{}, trying {:?}", err, config.frame_size());
+            camera.reconfigure(config)?;
+        }
+    }
+
+    let overlay = Overlay::cctv("espcam webserver");
 
     let mut server = EspHttpServer::new(&esp_idf_svc::http::server::Configuration::default())?;
 
     server.fn_handler("/camera.jpg", Method::Get, move |request| {
-        camera.get_framebuffer();
-        // take two frames to get a fresh one
-        let framebuffer = camera.get_framebuffer();
+        let framebuffer = camera.capture_fresh();
 
         if let Some(framebuffer) = framebuffer {
-            let data = framebuffer.data();
+            let exif = ExifData::new()
+                .with_device_name("espcam webserver")
+                .for_frame(&framebuffer, framebuffer.meta());
+            let exif = exif.clone().for_sensor(&camera.sensor()).unwrap_or(exif);
+
+            let stamped = overlay
+                .apply(&framebuffer, framebuffer.meta())
+                .inspect_err(|err| log::warn!("could not stamp the frame: {}", err));
+
+            // serve the frame as it is if there's no memory for the copy
+            let photo = match &stamped {
+                Ok(stamped) => frame_with_exif(stamped, &exif),
+                Err(_) => frame_with_exif(&framebuffer, &exif),
+            };
+            let data = match &photo {
+                Ok(photo) => photo,
+                Err(_) => framebuffer.data(),
+            };
 
             let headers = [
                 ("Content-Type", "image/jpeg"),
@@ -0,0 +1,379 @@
+use image::{GrayImage, ImageFormat, Luma, Rgb, RgbImage};
+
+use crate::espcam::{Frame, PixelFormat};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConvertError {
+    #[error("{0} frames can't be converted")]
+    UnsupportedFormat(PixelFormat),
+    #[error("frame buffer holds {actual} bytes, {expected} expected")]
+    BufferTooShort { expected: usize, actual: usize },
+    #[error("{0} frames pack two pixels together, a width of {1} can't be split")]
+    OddWidth(PixelFormat, usize),
+    #[error("jpeg decoding failed: {0}")]
+    Decode(#[from] image::ImageError),
+}
+
+/// How multi-byte pixels are laid out in the frame buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteOrder {
+    /// esp32-camera sensors send RGB565 high byte first
+    pub rgb565_big_endian: bool,
+    /// esp32-camera stores RGB888 as B, G, R like its own converters
+    pub rgb888_bgr: bool,
+}
+
+impl Default for ByteOrder {
+    fn default() -> Self {
+        Self {
+            rgb565_big_endian: true,
+            rgb888_bgr: true,
+        }
+    }
+}
+
+pub fn to_rgb(frame: &impl Frame) -> Result<RgbImage, ConvertError> {
+    rgb_from_raw(
+        frame.data(),
+        frame.width(),
+        frame.height(),
+        frame.format(),
+        ByteOrder::default(),
+    )
+}
+
+pub fn to_gray(frame: &impl Frame) -> Result<GrayImage, ConvertError> {
+    gray_from_raw(
+        frame.data(),
+        frame.width(),
+        frame.height(),
+        frame.format(),
+        ByteOrder::default(),
+    )
+}
+
+pub fn rgb_from_raw(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    order: ByteOrder,
+) -> Result<RgbImage, ConvertError> {
+    let (w, h) = (width as u32, height as u32);
+
+    match format {
+        PixelFormat::Jpeg => {
+            Ok(image::load_from_memory_with_format(data, ImageFormat::Jpeg)?.to_rgb8())
+        }
+        PixelFormat::Grayscale => {
+            check_len(data, format, width, height)?;
+            Ok(RgbImage::from_fn(w, h, |x, y| {
+                let luma = data[y as usize * width + x as usize];
+                Rgb([luma, luma, luma])
+            }))
+        }
+        PixelFormat::Rgb565 => {
+            check_len(data, format, width, height)?;
+            Ok(RgbImage::from_fn(w, h, |x, y| {
+                let i = (y as usize * width + x as usize) * 2;
+                let pair = [data[i], data[i + 1]];
+                let raw = if order.rgb565_big_endian {
+                    u16::from_be_bytes(pair)
+                } else {
+                    u16::from_le_bytes(pair)
+                };
+                rgb565_to_rgb(raw)
+            }))
+        }
+        PixelFormat::Rgb888 => {
+            check_len(data, format, width, height)?;
+            Ok(RgbImage::from_fn(w, h, |x, y| {
+                let i = (y as usize * width + x as usize) * 3;
+                if order.rgb888_bgr {
+                    Rgb([data[i + 2], data[i + 1], data[i]])
+                } else {
+                    Rgb([data[i], data[i + 1], data[i + 2]])
+                }
+            }))
+        }
+        PixelFormat::Yuv422 => {
+            check_len(data, format, width, height)?;
+            // packed Y0 U Y1 V, each chroma pair shared by two pixels
+            Ok(RgbImage::from_fn(w, h, |x, y| {
+                let pair = (y as usize * width + (x as usize & !1)) * 2;
+                let luma = data[pair + if x % 2 == 0 { 0 } else { 2 }];
+                yuv_to_rgb(luma, data[pair + 1], data[pair + 3])
+            }))
+        }
+        PixelFormat::Yuv420 => {
+            check_len(data, format, width, height)?;
+            // planar Y, then U and V at half resolution in both directions
+            let chroma_width = width.div_ceil(2);
+            let u_plane = width * height;
+            let v_plane = u_plane + chroma_width * height.div_ceil(2);
+            Ok(RgbImage::from_fn(w, h, |x, y| {
+                let luma = data[y as usize * width + x as usize];
+                let chroma = (y as usize / 2) * chroma_width + x as usize / 2;
+                yuv_to_rgb(luma, data[u_plane + chroma], data[v_plane + chroma])
+            }))
+        }
+        _ => Err(ConvertError::UnsupportedFormat(format)),
+    }
+}
+
+pub fn gray_from_raw(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    order: ByteOrder,
+) -> Result<GrayImage, ConvertError> {
+    let (w, h) = (width as u32, height as u32);
+
+    match format {
+        PixelFormat::Jpeg => {
+            Ok(image::load_from_memory_with_format(data, ImageFormat::Jpeg)?.to_luma8())
+        }
+        PixelFormat::Grayscale => {
+            check_len(data, format, width, height)?;
+            Ok(GrayImage::from_fn(w, h, |x, y| {
+                Luma([data[y as usize * width + x as usize]])
+            }))
+        }
+        // the luma plane is already there, no need to go through RGB
+        PixelFormat::Yuv422 => {
+            check_len(data, format, width, height)?;
+            Ok(GrayImage::from_fn(w, h, |x, y| {
+                Luma([data[(y as usize * width + x as usize) * 2]])
+            }))
+        }
+        PixelFormat::Yuv420 => {
+            check_len(data, format, width, height)?;
+            Ok(GrayImage::from_fn(w, h, |x, y| {
+                Luma([data[y as usize * width + x as usize]])
+            }))
+        }
+        _ => {
+            let rgb = rgb_from_raw(data, width, height, format, order)?;
+            Ok(GrayImage::from_fn(w, h, |x, y| {
+                let Rgb([r, g, b]) = *rgb.get_pixel(x, y);
+                Luma([luma(r, g, b)])
+            }))
+        }
+    }
+}
+
+/// Expands the 5 and 6 bit channels to the full 0..=255 range
+pub fn rgb565_to_rgb(raw: u16) -> Rgb<u8> {
+    let r = (raw >> 11) as u8 & 0x1F;
+    let g = (raw >> 5) as u8 & 0x3F;
+    let b = raw as u8 & 0x1F;
+    Rgb([r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2])
+}
+
+/// ITU-R BT.601 luma
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+/// Full range ITU-R BT.601, as produced by the OmniVision sensors
+pub fn yuv_to_rgb(y: u8, u: u8, v: u8) -> Rgb<u8> {
+    let y = y as i32;
+    let u = u as i32 - 128;
+    let v = v as i32 - 128;
+
+    let r = y + ((359 * v) >> 8);
+    let g = y - ((88 * u + 183 * v) >> 8);
+    let b = y + ((454 * u) >> 8);
+
+    Rgb([
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    ])
+}
+
+fn check_len(
+    data: &[u8],
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+) -> Result<(), ConvertError> {
+    // YUV422 rows are made of whole Y0 U Y1 V pairs
+    if format == PixelFormat::Yuv422 && width % 2 != 0 {
+        return Err(ConvertError::OddWidth(format, width));
+    }
+
+    let expected = match format {
+        // chroma planes round up for odd sizes
+        PixelFormat::Yuv420 => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+        _ => format
+            .frame_len(width, height)
+            .ok_or(ConvertError::UnsupportedFormat(format))?,
+    };
+
+    if data.len() < expected {
+        Err(ConvertError::BufferTooShort {
+            expected,
+            actual: data.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(image: &RgbImage) -> Vec<[u8; 3]> {
+        image.pixels().map(|pixel| pixel.0).collect()
+    }
+
+    fn gray(image: &GrayImage) -> Vec<u8> {
+        image.pixels().map(|pixel| pixel.0[0]).collect()
+    }
+
+    // red, green, blue, white
+    const RGB565: [u16; 4] = [0xF800, 0x07E0, 0x001F, 0xFFFF];
+    const PRIMARIES: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    #[test]
+    fn rgb565_big_endian() {
+        let data: Vec<u8> = RGB565.iter().flat_map(|raw| raw.to_be_bytes()).collect();
+        let image = rgb_from_raw(&data, 2, 2, PixelFormat::Rgb565, ByteOrder::default()).unwrap();
+        assert_eq!(rgb(&image), PRIMARIES);
+    }
+
+    #[test]
+    fn rgb565_little_endian() {
+        let data: Vec<u8> = RGB565.iter().flat_map(|raw| raw.to_le_bytes()).collect();
+        let order = ByteOrder {
+            rgb565_big_endian: false,
+            ..Default::default()
+        };
+        let image = rgb_from_raw(&data, 2, 2, PixelFormat::Rgb565, order).unwrap();
+        assert_eq!(rgb(&image), PRIMARIES);
+    }
+
+    #[test]
+    fn rgb565_mid_gray() {
+        // 16 of 31 and 32 of 63
+        assert_eq!(rgb565_to_rgb(0x8410).0, [132, 130, 132]);
+    }
+
+    #[test]
+    fn rgb888_bgr() {
+        let data = [0, 0, 255, 255, 0, 0];
+        let image = rgb_from_raw(&data, 2, 1, PixelFormat::Rgb888, ByteOrder::default()).unwrap();
+        assert_eq!(rgb(&image), [[255, 0, 0], [0, 0, 255]]);
+    }
+
+    #[test]
+    fn yuv422() {
+        // black and white sharing neutral chroma, then a red pair
+        let data = [0, 128, 255, 128, 76, 85, 76, 255];
+        let image = rgb_from_raw(&data, 2, 2, PixelFormat::Yuv422, ByteOrder::default()).unwrap();
+        assert_eq!(
+            rgb(&image),
+            [[0, 0, 0], [255, 255, 255], [254, 0, 0], [254, 0, 0]]
+        );
+
+        let image = gray_from_raw(&data, 2, 2, PixelFormat::Yuv422, ByteOrder::default()).unwrap();
+        assert_eq!(gray(&image), [0, 255, 76, 76]);
+    }
+
+    #[test]
+    fn yuv420() {
+        // 2x2 luma, one U and one V for all four pixels
+        let data = [10, 20, 30, 40, 128, 128];
+        let image = rgb_from_raw(&data, 2, 2, PixelFormat::Yuv420, ByteOrder::default()).unwrap();
+        assert_eq!(
+            rgb(&image),
+            [[10, 10, 10], [20, 20, 20], [30, 30, 30], [40, 40, 40]]
+        );
+
+        let image = gray_from_raw(&data, 2, 2, PixelFormat::Yuv420, ByteOrder::default()).unwrap();
+        assert_eq!(gray(&image), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn yuv420_odd_size() {
+        // 3x1 luma, chroma rounded up to 2x1
+        let data = [50, 60, 70, 128, 128, 128, 128];
+        let image = rgb_from_raw(&data, 3, 1, PixelFormat::Yuv420, ByteOrder::default()).unwrap();
+        assert_eq!(rgb(&image), [[50, 50, 50], [60, 60, 60], [70, 70, 70]]);
+    }
+
+    #[test]
+    fn yuv420_odd_width_and_height() {
+        // 3x3 luma, chroma rounded up to 2x2 per plane
+        let mut data = vec![90; 9];
+        data.extend([128; 8]);
+        let image = rgb_from_raw(&data, 3, 3, PixelFormat::Yuv420, ByteOrder::default()).unwrap();
+        assert_eq!(rgb(&image), [[90, 90, 90]; 9]);
+
+        let err = rgb_from_raw(&data[..16], 3, 3, PixelFormat::Yuv420, ByteOrder::default());
+        assert!(matches!(
+            err,
+            Err(ConvertError::BufferTooShort {
+                expected: 17,
+                actual: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn yuv422_odd_width() {
+        // 3x2 at two bytes a pixel, the last pair would run past the buffer
+        let data = [128; 12];
+        assert!(matches!(
+            rgb_from_raw(&data, 3, 2, PixelFormat::Yuv422, ByteOrder::default()),
+            Err(ConvertError::OddWidth(PixelFormat::Yuv422, 3))
+        ));
+        assert!(matches!(
+            gray_from_raw(&data, 3, 2, PixelFormat::Yuv422, ByteOrder::default()),
+            Err(ConvertError::OddWidth(PixelFormat::Yuv422, 3))
+        ));
+    }
+
+    #[test]
+    fn grayscale() {
+        let data = [0, 128, 255];
+        let image =
+            rgb_from_raw(&data, 3, 1, PixelFormat::Grayscale, ByteOrder::default()).unwrap();
+        assert_eq!(rgb(&image), [[0, 0, 0], [128, 128, 128], [255, 255, 255]]);
+
+        let image =
+            gray_from_raw(&data, 3, 1, PixelFormat::Grayscale, ByteOrder::default()).unwrap();
+        assert_eq!(gray(&image), data);
+    }
+
+    #[test]
+    fn gray_from_color() {
+        let data: Vec<u8> = RGB565.iter().flat_map(|raw| raw.to_be_bytes()).collect();
+        let image = gray_from_raw(&data, 2, 2, PixelFormat::Rgb565, ByteOrder::default()).unwrap();
+        assert_eq!(gray(&image), [76, 149, 29, 255]);
+    }
+
+    #[test]
+    fn short_buffer() {
+        let err = rgb_from_raw(&[0; 7], 2, 2, PixelFormat::Rgb565, ByteOrder::default());
+        assert!(matches!(
+            err,
+            Err(ConvertError::BufferTooShort {
+                expected: 8,
+                actual: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn unsupported_format() {
+        let err = rgb_from_raw(&[0; 8], 2, 2, PixelFormat::Raw, ByteOrder::default());
+        assert!(matches!(
+            err,
+            Err(ConvertError::UnsupportedFormat(PixelFormat::Raw))
+        ));
+    }
+}
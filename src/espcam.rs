@@ -1,346 +1,77 @@
-use std::marker::PhantomData;
-
-use esp_idf_hal::gpio::*;
-use esp_idf_sys::*;
-
-pub struct FrameBuffer<'a> {
-    fb: *mut camera::camera_fb_t,
-    _p: PhantomData<&'a camera::camera_fb_t>,
-}
-
-impl<'a> FrameBuffer<'a> {
-    pub fn data(&self) -> &'a [u8] {
-        unsafe { std::slice::from_raw_parts((*self.fb).buf, (*self.fb).len) }
-    }
-
-    pub fn width(&self) -> usize {
-        unsafe { (*self.fb).width }
-    }
-
-    pub fn height(&self) -> usize {
-        unsafe { (*self.fb).height }
-    }
-
-    pub fn format(&self) -> camera::pixformat_t {
-        unsafe { (*self.fb).format }
-    }
-
-    pub fn timestamp(&self) -> camera::timeval {
-        unsafe { (*self.fb).timestamp }
-    }
-
-    pub fn fb_return(&self) {
-        unsafe { camera::esp_camera_fb_return(self.fb) }
-    }
-}
-
-impl Drop for FrameBuffer<'_> {
-    fn drop(&mut self) {
-        self.fb_return();
-    }
-}
-
-pub struct CameraSensor<'a> {
-    sensor: *mut camera::sensor_t,
-    _p: PhantomData<&'a camera::sensor_t>,
-}
-
-impl<'a> CameraSensor<'a> {
-    pub fn init_status(&self) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).init_status.unwrap()(self.sensor) })
-    }
-    pub fn reset(&self) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).reset.unwrap()(self.sensor) })
-    }
-    pub fn set_pixformat(&self, format: camera::pixformat_t) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_pixformat.unwrap()(self.sensor, format) })
-    }
-    pub fn set_framesize(&self, framesize: camera::framesize_t) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_framesize.unwrap()(self.sensor, framesize) })
-    }
-    pub fn set_contrast(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_contrast.unwrap()(self.sensor, level) })
-    }
-    pub fn set_brightness(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_brightness.unwrap()(self.sensor, level) })
-    }
-    pub fn set_saturation(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_saturation.unwrap()(self.sensor, level) })
-    }
-    pub fn set_sharpness(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_sharpness.unwrap()(self.sensor, level) })
-    }
-    pub fn set_denoise(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_denoise.unwrap()(self.sensor, level) })
-    }
-    pub fn set_gainceiling(&self, gainceiling: camera::gainceiling_t) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_gainceiling.unwrap()(self.sensor, gainceiling) })
-    }
-    pub fn set_quality(&self, quality: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_quality.unwrap()(self.sensor, quality) })
-    }
-    pub fn set_colorbar(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_colorbar.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_whitebal(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_whitebal.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_gain_ctrl(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_gain_ctrl.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_exposure_ctrl(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_exposure_ctrl.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_hmirror(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_hmirror.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_vflip(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_vflip.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn set_aec2(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_aec2.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn set_awb_gain(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_awb_gain.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_agc_gain(&self, gain: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_agc_gain.unwrap()(self.sensor, gain) })
-    }
-    pub fn set_aec_value(&self, gain: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_aec_value.unwrap()(self.sensor, gain) })
-    }
-    pub fn set_special_effect(&self, effect: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_special_effect.unwrap()(self.sensor, effect) })
-    }
-    pub fn set_wb_mode(&self, mode: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_wb_mode.unwrap()(self.sensor, mode) })
-    }
-    pub fn set_ae_level(&self, level: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_ae_level.unwrap()(self.sensor, level) })
-    }
-    pub fn set_dcw(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_dcw.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn set_bpc(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_bpc.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn set_wpc(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_wpc.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn set_raw_gma(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_raw_gma.unwrap()(self.sensor, if enable { 1 } else { 0 })
-        })
-    }
-    pub fn set_lenc(&self, enable: bool) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_lenc.unwrap()(self.sensor, if enable { 1 } else { 0 }) })
-    }
-    pub fn get_reg(&self, reg: i32, mask: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).get_reg.unwrap()(self.sensor, reg, mask) })
-    }
-    pub fn set_reg(&self, reg: i32, mask: i32, value: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_reg.unwrap()(self.sensor, reg, mask, value) })
-    }
-    pub fn set_res_raw(
-        &self,
-        start_x: i32,
-        start_y: i32,
-        end_x: i32,
-        end_y: i32,
-        offset_x: i32,
-        offset_y: i32,
-        total_x: i32,
-        total_y: i32,
-        output_x: i32,
-        output_y: i32,
-        scale: bool,
-        binning: bool,
-    ) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_res_raw.unwrap()(
-                self.sensor,
-                start_x,
-                start_y,
-                end_x,
-                end_y,
-                offset_x,
-                offset_y,
-                total_x,
-                total_y,
-                output_x,
-                output_y,
-                scale,
-                binning,
-            )
-        })
-    }
-    pub fn set_pll(
-        &self,
-        bypass: i32,
-        mul: i32,
-        sys: i32,
-        root: i32,
-        pre: i32,
-        seld5: i32,
-        pclken: i32,
-        pclk: i32,
-    ) -> Result<(), EspError> {
-        esp!(unsafe {
-            (*self.sensor).set_pll.unwrap()(
-                self.sensor,
-                bypass,
-                mul,
-                sys,
-                root,
-                pre,
-                seld5,
-                pclken,
-                pclk,
-            )
-        })
-    }
-    pub fn set_xclk(&self, timer: i32, xclk: i32) -> Result<(), EspError> {
-        esp!(unsafe { (*self.sensor).set_xclk.unwrap()(self.sensor, timer, xclk) })
-    }
-}
-
-pub struct Camera<'a> {
-    _p: PhantomData<&'a ()>,
-}
-
-impl<'a> Camera<'a> {
-    pub fn apply_low_noise_profile(&self) {
-        let sensor = unsafe { camera::esp_camera_sensor_get() };
-        if sensor.is_null() {
-            return;
-        }
-
-        unsafe {
-            // A slower sensor clock lowers EMI and lets auto exposure favor longer
-            // integration over analog gain, which keeps JPEG size stable but cleaner.
-            if let Some(set_xclk) = (*sensor).set_xclk {
-                let _ = set_xclk(sensor, esp_idf_sys::ledc_timer_t_LEDC_TIMER_0 as i32, 10);
-            }
-
-            if let Some(set_gainceiling) = (*sensor).set_gainceiling {
-                let _ = set_gainceiling(sensor, 0 as camera::gainceiling_t);
-            }
-            if let Some(set_aec2) = (*sensor).set_aec2 {
-                let _ = set_aec2(sensor, 1);
-            }
-            if let Some(set_bpc) = (*sensor).set_bpc {
-                let _ = set_bpc(sensor, 1);
-            }
-            if let Some(set_wpc) = (*sensor).set_wpc {
-                let _ = set_wpc(sensor, 1);
-            }
-            if let Some(set_raw_gma) = (*sensor).set_raw_gma {
-                let _ = set_raw_gma(sensor, 1);
-            }
-            if let Some(set_lenc) = (*sensor).set_lenc {
-                let _ = set_lenc(sensor, 1);
-            }
-        }
-    }
-
-    pub fn new(
-        pin_pwdn: impl InputPin + OutputPin + 'a,
-        pin_xclk: impl InputPin + OutputPin + 'a,
-        pin_d0: impl InputPin + OutputPin + 'a,
-        pin_d1: impl InputPin + OutputPin + 'a,
-        pin_d2: impl InputPin + OutputPin + 'a,
-        pin_d3: impl InputPin + OutputPin + 'a,
-        pin_d4: impl InputPin + 'a,
-        pin_d5: impl InputPin + 'a,
-        pin_d6: impl InputPin + 'a,
-        pin_d7: impl InputPin + 'a,
-        pin_vsync: impl InputPin + OutputPin + 'a,
-        pin_href: impl InputPin + OutputPin + 'a,
-        pin_pclk: impl InputPin + OutputPin + 'a,
-        pin_sda: impl InputPin + OutputPin + 'a,
-        pin_scl: impl InputPin + OutputPin + 'a,
-        pixel_format: camera::pixformat_t,
-        frame_size: camera::framesize_t,
-    ) -> Result<Self, esp_idf_sys::EspError> {
-        let config = camera::camera_config_t {
-            pin_pwdn: pin_pwdn.pin() as i32,
-            pin_xclk: pin_xclk.pin() as i32,
-            pin_reset: 0xff,
-
-            pin_d0: pin_d0.pin() as i32,
-            pin_d1: pin_d1.pin() as i32,
-            pin_d2: pin_d2.pin() as i32,
-            pin_d3: pin_d3.pin() as i32,
-            pin_d4: pin_d4.pin() as i32,
-            pin_d5: pin_d5.pin() as i32,
-            pin_d6: pin_d6.pin() as i32,
-            pin_d7: pin_d7.pin() as i32,
-            pin_vsync: pin_vsync.pin() as i32,
-            pin_href: pin_href.pin() as i32,
-            pin_pclk: pin_pclk.pin() as i32,
-
-            xclk_freq_hz: 20000000,
-            ledc_timer: esp_idf_sys::ledc_timer_t_LEDC_TIMER_0,
-            ledc_channel: esp_idf_sys::ledc_channel_t_LEDC_CHANNEL_0,
-
-            pixel_format,
-            frame_size,
-
-            jpeg_quality: 12,
-            fb_count: 1,
-            grab_mode: camera::camera_grab_mode_t_CAMERA_GRAB_WHEN_EMPTY,
-
-            fb_location: camera::camera_fb_location_t_CAMERA_FB_IN_PSRAM,
-
-            __bindgen_anon_1: camera::camera_config_t__bindgen_ty_1 {
-                pin_sccb_sda: pin_sda.pin() as i32,
-            },
-            __bindgen_anon_2: camera::camera_config_t__bindgen_ty_2 {
-                pin_sccb_scl: pin_scl.pin() as i32,
-            },
-
-            ..Default::default()
-        };
-
-        esp_idf_sys::esp!(unsafe { camera::esp_camera_init(&config) })?;
-        Ok(Self { _p: PhantomData })
-    }
-
-    pub fn get_framebuffer(&self) -> Option<FrameBuffer<'_>> {
-        let fb = unsafe { camera::esp_camera_fb_get() };
-        if fb.is_null() {
-            //unsafe { camera::esp_camera_fb_return(fb); }
-            None
-        } else {
-            Some(FrameBuffer {
-                fb,
-                _p: PhantomData,
-            })
-        }
-    }
-
-    pub fn sensor(&self) -> CameraSensor<'a> {
-        CameraSensor {
-            sensor: unsafe { camera::esp_camera_sensor_get() },
-            _p: PhantomData,
-        }
-    }
-}
-
-impl<'a> Drop for Camera<'a> {
-    fn drop(&mut self) {
-        esp!(unsafe { camera::esp_camera_deinit() }).expect("error during esp_camera_deinit")
-    }
+mod clock;
+mod format;
+mod jpeg;
+mod model;
+mod roi;
+
+#[cfg(target_os = "espidf")]
+mod camera;
+#[cfg(target_os = "espidf")]
+mod codec;
+#[cfg(target_os = "espidf")]
+mod config;
+#[cfg(target_os = "espidf")]
+mod diagnostics;
+#[cfg(target_os = "espidf")]
+mod heap;
+#[cfg(target_os = "espidf")]
+mod mask;
+#[cfg(target_os = "espidf")]
+mod meta;
+#[cfg(target_os = "espidf")]
+mod owned;
+#[cfg(target_os = "espidf")]
+mod pins;
+#[cfg(target_os = "espidf")]
+mod profile;
+#[cfg(target_os = "espidf")]
+mod sensor;
+#[cfg(target_os = "espidf")]
+mod service;
+#[cfg(target_os = "espidf")]
+mod stream;
+
+pub use clock::{wall_clock_synced, DateTime, Weekday};
+pub use format::{FrameSize, PixelFormat};
+pub use jpeg::{jpeg_size, validate_jpeg, CorruptJpeg, JPEG_QUALITY_RANGE};
+pub use model::{SensorCapabilities, SensorControl, SensorModel};
+pub use roi::{native_resolution, DigitalZoom, RawWindow, Roi, RoiError};
+
+#[cfg(target_os = "espidf")]
+pub use camera::{uptime, Camera, CameraError, FrameBuffer};
+#[cfg(target_os = "espidf")]
+pub use codec::{
+    check_decode_memory, edit_jpeg, encode_jpeg, JpegBuffer, JpegError, JPEG_ENCODE_QUALITY_RANGE,
+};
+#[cfg(target_os = "espidf")]
+pub use config::{CameraConfig, CameraConfigError, FbLocation, GrabMode, XCLK_FREQ_RANGE};
+#[cfg(target_os = "espidf")]
+pub use diagnostics::psram_available;
+#[cfg(target_os = "espidf")]
+pub use format::{UnknownFrameSize, UnknownPixelFormat};
+#[cfg(target_os = "espidf")]
+pub use heap::HeapBuffer;
+#[cfg(target_os = "espidf")]
+pub use mask::{MaskError, MaskRegion, PrivacyMask};
+#[cfg(target_os = "espidf")]
+pub use meta::{FrameMeta, FrameStats};
+#[cfg(target_os = "espidf")]
+pub use owned::OwnedFrame;
+#[cfg(target_os = "espidf")]
+pub use pins::{Board, CameraPins};
+#[cfg(target_os = "espidf")]
+pub use profile::{ProfileRegistry, ProfileReport, SensorProfile, SensorSetting, SettingOutcome};
+#[cfg(target_os = "espidf")]
+pub use sensor::{CameraSensor, GainCeiling, SensorError, SensorSettings, UnknownGainCeiling};
+#[cfg(target_os = "espidf")]
+pub use service::{CameraService, LatencyStats, ServiceError, Subscription};
+#[cfg(target_os = "espidf")]
+pub use stream::{FrameStream, Overflow, StreamOptions};
+
+/// Image data with its geometry, borrowed from the driver or owned
+pub trait Frame {
+    fn data(&self) -> &[u8];
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn format(&self) -> PixelFormat;
 }
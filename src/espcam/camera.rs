@@ -0,0 +1,629 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use esp_idf_hal::gpio::{AnyOutputPin, Pin};
+use esp_idf_sys::{camera, esp, esp_timer_get_time, gpio_set_level, EspError};
+
+use super::meta::{self, FrameCounter};
+use super::{
+    diagnostics, encode_jpeg, validate_jpeg, CameraConfig, CameraConfigError, CameraPins,
+    CameraSensor, CorruptJpeg, FbLocation, Frame, FrameMeta, FrameSize, FrameStats, FrameStream,
+    HeapBuffer, JpegBuffer, JpegError, MaskError, OwnedFrame, PixelFormat, PrivacyMask,
+    ProfileReport, SensorError, SensorModel, SensorProfile, SensorSettings, StreamOptions,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CameraError {
+    #[error("invalid camera config: {0}")]
+    Config(#[from] CameraConfigError),
+    #[error("camera driver error: {0}")]
+    Esp(#[from] EspError),
+    #[error("the camera is already initialized")]
+    AlreadyTaken,
+    #[error("sensor error: {0}")]
+    Sensor(#[from] SensorError),
+    #[error("the board has no PWDN pin to put the sensor in standby")]
+    NoPowerDownPin,
+    #[error("no sensor answered on SCCB, check the wiring and the pwdn/reset pins")]
+    NoSensor,
+    #[error("the sensor isn't supported by the driver")]
+    UnsupportedSensor,
+    #[error("framebuffers are configured in PSRAM but the board has none")]
+    PsramMissing,
+    #[error("could not allocate {count} framebuffer(s) in {location:?}")]
+    FrameBufferAlloc { count: usize, location: FbLocation },
+    #[error("{model:?} can't output {requested}, the largest it does is {max}")]
+    UnsupportedFrameSize {
+        model: SensorModel,
+        requested: FrameSize,
+        max: FrameSize,
+    },
+    #[error(
+        "reinitializing failed ({error}), and so did restoring the previous config ({rollback})"
+    )]
+    Rollback {
+        error: Box<CameraError>,
+        rollback: Box<CameraError>,
+    },
+    #[error("every fallback config failed: {0:?}")]
+    Fallback(Vec<(CameraConfig, CameraError)>),
+    #[error("privacy mask error: {0}")]
+    Mask(#[from] MaskError),
+}
+
+// esp32-camera keeps a single global driver instance
+static CAMERA_TAKEN: AtomicBool = AtomicBool::new(false);
+
+// extra frames capture_fresh may skip on top of the queued ones
+const FRESH_CAPTURE_RETRIES: usize = 3;
+
+const SENSOR_WAKE_DELAY: Duration = Duration::from_millis(10);
+
+/// Time since boot, on the same clock the driver stamps frames with
+pub fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
+/// A frame borrowed from the driver, handed back to it when dropped.
+///
+/// The pixel data can't outlive the frame:
+///
+/// ```compile_fail,E0515
+/// # use espcam::espcam::Camera;
+/// fn leak<'c>(camera: &'c Camera) -> &'c [u8] {
+///     let frame = camera.get_framebuffer().unwrap();
+///     frame.data()
+/// }
+/// ```
+///
+/// The frame is only returned once, by dropping or releasing it:
+///
+/// ```compile_fail,E0624
+/// # use espcam::espcam::Camera;
+/// fn double_return(camera: &Camera) {
+///     let frame = camera.get_framebuffer().unwrap();
+///     frame.fb_return();
+/// }
+/// ```
+///
+/// ```compile_fail,E0505
+/// # use espcam::espcam::Camera;
+/// fn use_after_release(camera: &Camera) {
+///     let frame = camera.get_framebuffer().unwrap();
+///     let data = frame.data();
+///     frame.release();
+///     println!("{}", data.len());
+/// }
+/// ```
+///
+/// And the camera can't be deinitialized while a frame is out:
+///
+/// ```compile_fail,E0505
+/// # use espcam::espcam::Camera;
+/// fn outlive_camera(camera: Camera) {
+///     let frame = camera.get_framebuffer().unwrap();
+///     drop(camera);
+///     println!("{}", frame.width());
+/// }
+/// ```
+///
+/// ```compile_fail,E0515
+/// # use espcam::espcam::{Camera, CameraSensor};
+/// fn sensor_outlives_camera<'c>(camera: Camera<'c>) -> CameraSensor<'c> {
+///     camera.sensor()
+/// }
+/// ```
+pub struct FrameBuffer<'a> {
+    fb: *mut camera::camera_fb_t,
+    meta: FrameMeta,
+    // JPEG frames can't be masked in the driver's buffer, the copy is served instead
+    masked: Option<JpegBuffer>,
+    _p: PhantomData<&'a camera::camera_fb_t>,
+}
+
+impl<'a> FrameBuffer<'a> {
+    pub fn data(&self) -> &[u8] {
+        match &self.masked {
+            Some(masked) => masked,
+            None => unsafe { std::slice::from_raw_parts((*self.fb).buf, (*self.fb).len) },
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        unsafe { (*self.fb).width }
+    }
+
+    pub fn height(&self) -> usize {
+        unsafe { (*self.fb).height }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        // the driver only produces formats that were configured through PixelFormat
+        PixelFormat::try_from(unsafe { (*self.fb).format }).unwrap_or(PixelFormat::Raw)
+    }
+
+    /// Capture time since boot
+    pub fn timestamp(&self) -> Duration {
+        self.meta.timestamp
+    }
+
+    /// Capture time on the wall clock, `None` until the time is synced
+    pub fn wall_time(&self) -> Option<SystemTime> {
+        self.meta.wall_time()
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.meta.sequence
+    }
+
+    /// Sensor frames estimated to have gone by since the previous frame
+    pub fn dropped(&self) -> u32 {
+        self.meta.dropped
+    }
+
+    pub fn meta(&self) -> &FrameMeta {
+        &self.meta
+    }
+
+    /// Copies the frame out of the driver's buffer, see [`OwnedFrame`]
+    pub fn to_owned(&self) -> Result<OwnedFrame, EspError> {
+        Ok(OwnedFrame::new(
+            HeapBuffer::copy_from(self.data())?,
+            self.width(),
+            self.height(),
+            self.format(),
+            self.meta,
+        ))
+    }
+
+    /// Encodes a raw frame, see [`encode_jpeg`]
+    pub fn to_jpeg(&self, quality: u8) -> Result<JpegBuffer, JpegError> {
+        encode_jpeg(self, quality)
+    }
+
+    /// Checks the JPEG structure, see [`validate_jpeg`], and cuts off
+    /// anything after EOI
+    pub fn validate_jpeg(&mut self) -> Result<(), CorruptJpeg> {
+        let len = validate_jpeg(self.data())?;
+        // the driver resets len for the next capture, shortening it is harmless,
+        // a masked copy comes from the encoder and ends at EOI already
+        if self.masked.is_none() {
+            unsafe { (*self.fb).len = len };
+        }
+        Ok(())
+    }
+
+    /// Hands the buffer back to the driver, same as dropping it
+    pub fn release(self) {}
+
+    fn apply_mask(&mut self, mask: &PrivacyMask) -> Result<(), MaskError> {
+        if mask.is_empty() {
+            return Ok(());
+        }
+
+        let (width, height) = (self.width(), self.height());
+        match self.format() {
+            PixelFormat::Jpeg => {
+                self.masked = Some(mask.apply_jpeg(self.data())?);
+                Ok(())
+            }
+            format => {
+                let data =
+                    unsafe { std::slice::from_raw_parts_mut((*self.fb).buf, (*self.fb).len) };
+                mask.apply_raw(data, width, height, format)
+            }
+        }
+    }
+
+    fn fb_return(&mut self) {
+        unsafe { camera::esp_camera_fb_return(self.fb) }
+    }
+}
+
+impl Frame for FrameBuffer<'_> {
+    fn data(&self) -> &[u8] {
+        FrameBuffer::data(self)
+    }
+
+    fn width(&self) -> usize {
+        FrameBuffer::width(self)
+    }
+
+    fn height(&self) -> usize {
+        FrameBuffer::height(self)
+    }
+
+    fn format(&self) -> PixelFormat {
+        FrameBuffer::format(self)
+    }
+}
+
+impl Drop for FrameBuffer<'_> {
+    fn drop(&mut self) {
+        self.fb_return();
+    }
+}
+
+pub struct Camera<'a> {
+    pins: CameraPins<'a>,
+    config: CameraConfig,
+    settle_until: Mutex<Option<Duration>>,
+    // settings to restore on wake, Some while in standby
+    standby: Mutex<Option<SensorSettings>>,
+    in_standby: AtomicBool,
+    frames: Mutex<FrameCounter>,
+    privacy: Mutex<PrivacyMask>,
+}
+
+impl<'a> Camera<'a> {
+    pub fn apply_profile(&self, profile: &SensorProfile) -> ProfileReport {
+        let report = profile.apply(&self.sensor());
+        self.settle(self.config.settle_time());
+        report
+    }
+
+    pub fn apply_low_noise_profile(&self) -> ProfileReport {
+        self.apply_profile(&SensorProfile::low_noise())
+    }
+
+    pub fn new(pins: CameraPins<'a>, config: CameraConfig) -> Result<Self, CameraError> {
+        config.validate()?;
+
+        if CAMERA_TAKEN.swap(true, Ordering::SeqCst) {
+            return Err(CameraError::AlreadyTaken);
+        }
+
+        if let Err(err) = init_driver(&pins, &config) {
+            CAMERA_TAKEN.store(false, Ordering::SeqCst);
+            return Err(err);
+        }
+        Ok(Self::started(pins, config))
+    }
+
+    /// Tries the configs in order until one comes up, e.g. UXGA in PSRAM, then
+    /// SVGA, then QVGA in DRAM for boards with flaky PSRAM. Gives up early when
+    /// there is no usable sensor at all.
+    pub fn with_fallback(
+        pins: CameraPins<'a>,
+        configs: impl IntoIterator<Item = CameraConfig>,
+    ) -> Result<Self, CameraError> {
+        if CAMERA_TAKEN.swap(true, Ordering::SeqCst) {
+            return Err(CameraError::AlreadyTaken);
+        }
+
+        let mut failures = Vec::new();
+        for config in configs {
+            let result = config
+                .validate()
+                .map_err(CameraError::from)
+                .and_then(|()| init_driver(&pins, &config));
+
+            match result {
+                Ok(()) => {
+                    if !failures.is_empty() {
+                        log::warn!("camera started with fallback config {:?}", config);
+                    }
+                    return Ok(Self::started(pins, config));
+                }
+                Err(err @ (CameraError::NoSensor | CameraError::UnsupportedSensor)) => {
+                    failures.push((config, err));
+                    break;
+                }
+                Err(err) => failures.push((config, err)),
+            }
+        }
+
+        CAMERA_TAKEN.store(false, Ordering::SeqCst);
+        Err(CameraError::Fallback(failures))
+    }
+
+    // the driver is up, wrap it
+    fn started(pins: CameraPins<'a>, config: CameraConfig) -> Self {
+        Self {
+            pins,
+            config,
+            settle_until: Mutex::new(None),
+            standby: Mutex::new(None),
+            in_standby: AtomicBool::new(false),
+            frames: Mutex::new(FrameCounter::default()),
+            privacy: Mutex::new(PrivacyMask::new()),
+        }
+    }
+
+    /// Restarts the driver with another config, keeping the pins. If the new
+    /// config doesn't work, or the privacy mask can't be applied to its
+    /// frames, the previous one is brought back before returning the error.
+    /// Sensor settings go back to their defaults either way.
+    pub fn reconfigure(&mut self, config: CameraConfig) -> Result<(), CameraError> {
+        config.validate()?;
+
+        esp!(unsafe { camera::esp_camera_deinit() })?;
+
+        if let Err(error) = init_driver(&self.pins, &config) {
+            return self.rollback(error);
+        }
+        // the new frame buffers are in place, see what's left for decoding
+        let fits = self
+            .privacy
+            .lock()
+            .unwrap()
+            .check_memory(config.pixel_format(), config.frame_size());
+        if let Err(error) = fits {
+            esp!(unsafe { camera::esp_camera_deinit() })?;
+            return self.rollback(error.into());
+        }
+
+        // init powers the sensor up again
+        self.standby.lock().unwrap().take();
+        self.in_standby.store(false, Ordering::SeqCst);
+
+        self.config = config;
+        Ok(())
+    }
+
+    // brings the current config back after `error`
+    fn rollback(&self, error: CameraError) -> Result<(), CameraError> {
+        match init_driver(&self.pins, &self.config) {
+            Ok(()) => Err(error),
+            Err(rollback) => Err(CameraError::Rollback {
+                error: Box::new(error),
+                rollback: Box::new(rollback),
+            }),
+        }
+    }
+
+    /// Powers the sensor down through PWDN between captures, keeping the
+    /// driver and its buffers. The sensor settings are restored by [`wake`](Self::wake).
+    pub fn standby(&self) -> Result<(), CameraError> {
+        let pwdn = self.pwdn()?;
+
+        let mut standby = self.standby.lock().unwrap();
+        if standby.is_some() {
+            return Ok(());
+        }
+
+        let settings = self.sensor().settings()?;
+        esp!(unsafe { gpio_set_level(pwdn.pin() as _, 1) })?;
+
+        *standby = Some(settings);
+        self.in_standby.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn wake(&self) -> Result<(), CameraError> {
+        let pwdn = self.pwdn()?;
+
+        let mut standby = self.standby.lock().unwrap();
+        let Some(settings) = standby.as_ref() else {
+            return Ok(());
+        };
+
+        esp!(unsafe { gpio_set_level(pwdn.pin() as _, 0) })?;
+        // SCCB doesn't answer right after power up
+        std::thread::sleep(SENSOR_WAKE_DELAY);
+        self.sensor().apply_settings(settings)?;
+
+        standby.take();
+        self.in_standby.store(false, Ordering::SeqCst);
+        self.settle(self.config.settle_time());
+        Ok(())
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.in_standby.load(Ordering::SeqCst)
+    }
+
+    // on presets without PWDN the sensor is always powered, whatever pin is set
+    fn pwdn(&self) -> Result<&AnyOutputPin<'a>, CameraError> {
+        if self.pins.board.is_some_and(|board| !board.has_pwdn()) {
+            return Err(CameraError::NoPowerDownPin);
+        }
+        self.pins.pwdn.as_ref().ok_or(CameraError::NoPowerDownPin)
+    }
+
+    /// The next frame from the driver, with the privacy mask applied
+    pub fn get_framebuffer(&self) -> Option<FrameBuffer<'_>> {
+        self.masked(self.unmasked_framebuffer()?)
+    }
+
+    fn unmasked_framebuffer(&self) -> Option<FrameBuffer<'_>> {
+        // no frames come in while the sensor is down, don't wait for the driver timeout
+        if self.is_standby() {
+            return None;
+        }
+
+        let fb = unsafe { camera::esp_camera_fb_get() };
+        if fb.is_null() {
+            //unsafe { camera::esp_camera_fb_return(fb); }
+            None
+        } else {
+            let (timestamp, len) =
+                unsafe { (meta::timeval_to_duration((*fb).timestamp), (*fb).len) };
+            Some(FrameBuffer {
+                fb,
+                meta: self.frames.lock().unwrap().next(timestamp, len),
+                masked: None,
+                _p: PhantomData,
+            })
+        }
+    }
+
+    // a frame that can't be masked doesn't leave the driver
+    fn masked<'f>(&self, mut framebuffer: FrameBuffer<'f>) -> Option<FrameBuffer<'f>> {
+        if let Err(err) = framebuffer.apply_mask(&self.privacy.lock().unwrap()) {
+            log::warn!("dropping frame {}: {}", framebuffer.sequence(), err);
+            return None;
+        }
+        Some(framebuffer)
+    }
+
+    /// Makes the next [`capture_fresh`](Self::capture_fresh) wait `delay`
+    /// from now, for the sensor to adjust to a setting change or the flash
+    pub fn settle(&self, delay: Duration) {
+        let deadline = uptime() + delay;
+        let mut settle_until = self.settle_until.lock().unwrap();
+        *settle_until = Some(settle_until.map_or(deadline, |current| current.max(deadline)));
+    }
+
+    /// Returns a frame that started exposing after this call, skipping the
+    /// ones already queued in the driver.
+    ///
+    /// With [`GrabMode::Latest`] and more than one buffer the first frame is
+    /// usually fresh already, with [`GrabMode::WhenEmpty`] the stale buffers
+    /// are dropped until a new one comes in.
+    ///
+    /// JPEG frames are checked and captured again when corrupt if the config
+    /// sets [`CameraConfig::with_jpeg_retries`].
+    ///
+    /// Only the frame that is returned goes through the privacy mask.
+    pub fn capture_fresh(&self) -> Option<FrameBuffer<'_>> {
+        let mut framebuffer = self.fresh_framebuffer()?;
+
+        let retries = self.config.jpeg_retries();
+        if retries == 0 || framebuffer.format() != PixelFormat::Jpeg {
+            return self.masked(framebuffer);
+        }
+
+        for attempt in 0..=retries {
+            match framebuffer.validate_jpeg() {
+                Ok(()) => return self.masked(framebuffer),
+                Err(err) => log::warn!("corrupt frame {}: {}", framebuffer.sequence(), err),
+            }
+            if attempt == retries {
+                break;
+            }
+            // with a single buffer the next frame only comes once this one is back
+            drop(framebuffer);
+            framebuffer = self.unmasked_framebuffer()?;
+        }
+
+        None
+    }
+
+    fn fresh_framebuffer(&self) -> Option<FrameBuffer<'_>> {
+        let deadline = self.settle_until.lock().unwrap().take();
+        if let Some(deadline) = deadline {
+            let now = uptime();
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            }
+        }
+
+        let requested = uptime();
+
+        for _ in 0..self.config.fb_count() + FRESH_CAPTURE_RETRIES {
+            let framebuffer = self.unmasked_framebuffer()?;
+            if framebuffer.timestamp() >= requested {
+                return Some(framebuffer);
+            }
+        }
+
+        None
+    }
+
+    pub fn sensor(&self) -> CameraSensor<'_> {
+        CameraSensor::new(
+            unsafe { camera::esp_camera_sensor_get() },
+            self.config.ledc().0,
+        )
+    }
+
+    pub fn config(&self) -> &CameraConfig {
+        &self.config
+    }
+
+    /// Blacks out the mask's regions in every frame from now on, whatever
+    /// path it's captured through. The mask is kept across
+    /// [`reconfigure`](Self::reconfigure) and scales to the new frame size.
+    ///
+    /// JPEG frames are decoded and encoded again, which needs 3 bytes per
+    /// pixel in a single free block. A mask is refused when there is no
+    /// block that large at the configured frame size, and the odd frame that
+    /// still can't be masked is dropped.
+    ///
+    /// Planar YUV420 frames can't be masked at all, the mask is refused for them.
+    pub fn set_privacy_mask(&self, mask: PrivacyMask) -> Result<(), MaskError> {
+        mask.check_memory(self.config.pixel_format(), self.config.frame_size())?;
+        *self.privacy.lock().unwrap() = mask;
+        Ok(())
+    }
+
+    pub fn privacy_mask(&self) -> PrivacyMask {
+        self.privacy.lock().unwrap().clone()
+    }
+
+    /// Frame rate and sizes over the last frames taken from the driver
+    pub fn stats(&self) -> FrameStats {
+        self.frames.lock().unwrap().stats()
+    }
+}
+
+impl Camera<'static> {
+    /// Captures on a blocking worker thread and hands the frames to async code.
+    /// Don't grab frames some other way while the stream is alive.
+    ///
+    /// ```ignore
+    /// let mut frames = camera.frames(StreamOptions::default().with_fps(5.0))?;
+    /// while let Some(frame) = frames.next().await { /* ... */ }
+    /// ```
+    pub fn frames(self: &Arc<Self>, options: StreamOptions) -> std::io::Result<FrameStream> {
+        FrameStream::start(self.clone(), options)
+    }
+}
+
+impl<'a> Drop for Camera<'a> {
+    fn drop(&mut self) {
+        // fails when a reconfigure left the driver down, nothing to clean up then
+        if let Err(err) = esp!(unsafe { camera::esp_camera_deinit() }) {
+            log::warn!("esp_camera_deinit failed: {}", err);
+        }
+        CAMERA_TAKEN.store(false, Ordering::SeqCst);
+    }
+}
+
+fn init_driver(pins: &CameraPins, config: &CameraConfig) -> Result<(), CameraError> {
+    diagnostics::check_before_init(pins.board, config)?;
+
+    let mut raw = config.to_raw();
+
+    raw.pin_pwdn = pins.pwdn.as_ref().map_or(-1, |pin| pin.pin() as i32);
+    raw.pin_reset = pins.reset.as_ref().map_or(-1, |pin| pin.pin() as i32);
+    raw.pin_xclk = pins.xclk.pin() as i32;
+
+    raw.pin_d0 = pins.d0.pin() as i32;
+    raw.pin_d1 = pins.d1.pin() as i32;
+    raw.pin_d2 = pins.d2.pin() as i32;
+    raw.pin_d3 = pins.d3.pin() as i32;
+    raw.pin_d4 = pins.d4.pin() as i32;
+    raw.pin_d5 = pins.d5.pin() as i32;
+    raw.pin_d6 = pins.d6.pin() as i32;
+    raw.pin_d7 = pins.d7.pin() as i32;
+    raw.pin_vsync = pins.vsync.pin() as i32;
+    raw.pin_href = pins.href.pin() as i32;
+    raw.pin_pclk = pins.pclk.pin() as i32;
+
+    // without SCCB pins the driver talks to the sensor through sccb_i2c_port
+    let (pin_sda, pin_scl) = match (&pins.sda, &pins.scl) {
+        (Some(sda), Some(scl)) => (sda.pin() as i32, scl.pin() as i32),
+        _ => (-1, -1),
+    };
+    raw.__bindgen_anon_1 = camera::camera_config_t__bindgen_ty_1 {
+        pin_sccb_sda: pin_sda,
+    };
+    raw.__bindgen_anon_2 = camera::camera_config_t__bindgen_ty_2 {
+        pin_sccb_scl: pin_scl,
+    };
+
+    esp!(unsafe { camera::esp_camera_init(&raw) })
+        .map_err(|err| diagnostics::diagnose(err, config))?;
+
+    let sensor = CameraSensor::new(unsafe { camera::esp_camera_sensor_get() }, config.ledc().0);
+    if let Err(err) = diagnostics::check_after_init(&sensor, config) {
+        unsafe { camera::esp_camera_deinit() };
+        return Err(err);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use esp_idf_sys::{camera, heap_caps_get_largest_free_block, MALLOC_CAP_8BIT};
+
+use super::{jpeg_size, CorruptJpeg, Frame, HeapBuffer, PixelFormat};
+
+pub const JPEG_ENCODE_QUALITY_RANGE: std::ops::RangeInclusive<u8> = 1..=100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum JpegError {
+    #[error("{0} frames can't be encoded to jpeg")]
+    UnsupportedFormat(PixelFormat),
+    #[error("jpeg quality {0} is out of range 1..=100")]
+    InvalidQuality(u8),
+    #[error("frame is too large to encode")]
+    TooLarge,
+    #[error("jpeg encoding failed, most likely out of memory")]
+    EncodeFailed,
+    #[error("{0}")]
+    Corrupt(#[from] CorruptJpeg),
+    #[error("decoding the jpeg needs {needed} bytes, the largest free block is {available}")]
+    NoMemory { needed: usize, available: usize },
+    #[error("jpeg decoding failed")]
+    DecodeFailed,
+}
+
+/// JPEG bytes allocated by the esp32-camera encoder
+pub type JpegBuffer = HeapBuffer;
+
+/// Pixels decoded or drawn outside the driver, handed to the encoder
+struct RawFrame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+}
+
+impl Frame for RawFrame<'_> {
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn format(&self) -> PixelFormat {
+        self.format
+    }
+}
+
+/// Encodes a raw RGB565, YUV422, GRAYSCALE or RGB888 frame.
+///
+/// `quality` goes from 1 to 100, higher is better; unlike the sensor setting.
+pub fn encode_jpeg(frame: &impl Frame, quality: u8) -> Result<JpegBuffer, JpegError> {
+    let format = frame.format();
+    if !matches!(
+        format,
+        PixelFormat::Rgb565 | PixelFormat::Yuv422 | PixelFormat::Grayscale | PixelFormat::Rgb888
+    ) {
+        return Err(JpegError::UnsupportedFormat(format));
+    }
+    if !JPEG_ENCODE_QUALITY_RANGE.contains(&quality) {
+        return Err(JpegError::InvalidQuality(quality));
+    }
+    let (Ok(width), Ok(height)) = (u16::try_from(frame.width()), u16::try_from(frame.height()))
+    else {
+        return Err(JpegError::TooLarge);
+    };
+
+    let data = frame.data();
+    let mut buf = std::ptr::null_mut();
+    let mut len = 0;
+
+    // the encoder only reads from src, it's not const in the C signature
+    let ok = unsafe {
+        camera::fmt2jpg(
+            data.as_ptr() as *mut u8,
+            data.len(),
+            width,
+            height,
+            format.into(),
+            quality,
+            &mut buf,
+            &mut len,
+        )
+    };
+
+    if !ok || buf.is_null() {
+        return Err(JpegError::EncodeFailed);
+    }
+    Ok(unsafe { HeapBuffer::from_raw(buf, len) })
+}
+
+/// Decodes the JPEG, hands `edit` the pixels with their width and height and
+/// encodes the result again. The pixels are RGB888 laid out B, G, R, like
+/// the esp32-camera converters.
+///
+/// The decoded frame takes 3 bytes per pixel in a single block, from PSRAM
+/// when there is some, and the round trip takes a while on large frames.
+pub fn edit_jpeg<E: From<JpegError>>(
+    jpeg: &[u8],
+    quality: u8,
+    edit: impl FnOnce(&mut [u8], usize, usize) -> Result<(), E>,
+) -> Result<JpegBuffer, E> {
+    if !JPEG_ENCODE_QUALITY_RANGE.contains(&quality) {
+        return Err(JpegError::InvalidQuality(quality).into());
+    }
+
+    // the decoder writes as many pixels as the header says, size the buffer from it
+    let (width, height) = jpeg_size(jpeg).map_err(JpegError::from)?;
+    let needed = width * height * 3;
+    let mut decoded = HeapBuffer::psram(needed).map_err(|_| JpegError::NoMemory {
+        needed,
+        available: largest_free_block(),
+    })?;
+
+    let ok = unsafe {
+        camera::fmt2rgb888(
+            jpeg.as_ptr(),
+            jpeg.len(),
+            PixelFormat::Jpeg.into(),
+            decoded.as_mut_ptr(),
+        )
+    };
+    if !ok {
+        return Err(JpegError::DecodeFailed.into());
+    }
+
+    edit(&mut decoded, width, height)?;
+
+    let raw = RawFrame {
+        data: &decoded,
+        width,
+        height,
+        format: PixelFormat::Rgb888,
+    };
+    Ok(encode_jpeg(&raw, quality)?)
+}
+
+/// Checks that a `width` by `height` frame fits in the largest free block
+/// once decoded by [`edit_jpeg`], without allocating it
+pub fn check_decode_memory(width: usize, height: usize) -> Result<(), JpegError> {
+    let needed = width * height * 3;
+    let available = largest_free_block();
+    if needed > available {
+        return Err(JpegError::NoMemory { needed, available });
+    }
+    Ok(())
+}
+
+fn largest_free_block() -> usize {
+    unsafe { heap_caps_get_largest_free_block(MALLOC_CAP_8BIT) }
+}
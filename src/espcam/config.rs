@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use esp_idf_sys::{camera, ledc_channel_t, ledc_timer_t};
+
+use super::{FrameSize, PixelFormat, JPEG_QUALITY_RANGE};
+
+pub const XCLK_FREQ_RANGE: std::ops::RangeInclusive<u32> = 1_000_000..=40_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrabMode {
+    /// Fill buffers only when they are empty, frames can be stale
+    WhenEmpty,
+    /// Keep overwriting buffers so the newest frame is always returned
+    Latest,
+}
+
+impl From<GrabMode> for camera::camera_grab_mode_t {
+    fn from(value: GrabMode) -> Self {
+        match value {
+            GrabMode::WhenEmpty => camera::camera_grab_mode_t_CAMERA_GRAB_WHEN_EMPTY,
+            GrabMode::Latest => camera::camera_grab_mode_t_CAMERA_GRAB_LATEST,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FbLocation {
+    Psram,
+    Dram,
+}
+
+impl From<FbLocation> for camera::camera_fb_location_t {
+    fn from(value: FbLocation) -> Self {
+        match value {
+            FbLocation::Psram => camera::camera_fb_location_t_CAMERA_FB_IN_PSRAM,
+            FbLocation::Dram => camera::camera_fb_location_t_CAMERA_FB_IN_DRAM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CameraConfigError {
+    #[error("xclk frequency {0} Hz is out of range")]
+    InvalidXclkFreq(u32),
+    #[error("jpeg quality {0} is out of range 0..=63")]
+    InvalidJpegQuality(u8),
+    #[error("at least one frame buffer is required")]
+    NoFrameBuffers,
+    #[error("{0} frame buffers need PSRAM, DRAM only fits one")]
+    MultipleFbsInDram(usize),
+    #[error("ledc timer {0} does not exist")]
+    InvalidLedcTimer(ledc_timer_t),
+    #[error("ledc channel {0} does not exist")]
+    InvalidLedcChannel(ledc_channel_t),
+    #[error("i2c port {0} is invalid")]
+    InvalidSccbPort(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraConfig {
+    pixel_format: PixelFormat,
+    frame_size: FrameSize,
+    xclk_freq_hz: u32,
+    jpeg_quality: u8,
+    fb_count: usize,
+    fb_location: FbLocation,
+    grab_mode: GrabMode,
+    ledc_timer: ledc_timer_t,
+    ledc_channel: ledc_channel_t,
+    sccb_i2c_port: i32,
+    settle_time: Duration,
+    jpeg_retries: u8,
+}
+
+impl CameraConfig {
+    pub fn new(pixel_format: PixelFormat, frame_size: FrameSize) -> Self {
+        Self {
+            pixel_format,
+            frame_size,
+            xclk_freq_hz: 20_000_000,
+            jpeg_quality: 12,
+            fb_count: 1,
+            fb_location: FbLocation::Psram,
+            grab_mode: GrabMode::WhenEmpty,
+            ledc_timer: esp_idf_sys::ledc_timer_t_LEDC_TIMER_0,
+            ledc_channel: esp_idf_sys::ledc_channel_t_LEDC_CHANNEL_0,
+            sccb_i2c_port: 0,
+            settle_time: Duration::ZERO,
+            jpeg_retries: 0,
+        }
+    }
+
+    pub fn with_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    pub fn with_frame_size(mut self, frame_size: FrameSize) -> Self {
+        self.frame_size = frame_size;
+        self
+    }
+
+    pub fn with_xclk_freq_hz(mut self, xclk_freq_hz: u32) -> Self {
+        self.xclk_freq_hz = xclk_freq_hz;
+        self
+    }
+
+    /// 0 is the best quality, 63 the worst
+    pub fn with_jpeg_quality(mut self, jpeg_quality: u8) -> Self {
+        self.jpeg_quality = jpeg_quality;
+        self
+    }
+
+    pub fn with_fb_count(mut self, fb_count: usize) -> Self {
+        self.fb_count = fb_count;
+        self
+    }
+
+    pub fn with_fb_location(mut self, fb_location: FbLocation) -> Self {
+        self.fb_location = fb_location;
+        self
+    }
+
+    pub fn with_grab_mode(mut self, grab_mode: GrabMode) -> Self {
+        self.grab_mode = grab_mode;
+        self
+    }
+
+    /// LEDC timer and channel used to generate XCLK
+    pub fn with_ledc(mut self, timer: ledc_timer_t, channel: ledc_channel_t) -> Self {
+        self.ledc_timer = timer;
+        self.ledc_channel = channel;
+        self
+    }
+
+    /// I2C port of an already initialised bus, used when the camera pins
+    /// don't include SDA/SCL
+    pub fn with_sccb_i2c_port(mut self, port: i32) -> Self {
+        self.sccb_i2c_port = port;
+        self
+    }
+
+    /// How long `Camera::capture_fresh` waits after a profile is applied, for
+    /// auto exposure and white balance to catch up
+    pub fn with_settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+        self
+    }
+
+    /// Makes `Camera::capture_fresh` check JPEG frames and capture again up to
+    /// `retries` times when one is corrupt. With 0 frames aren't checked.
+    pub fn with_jpeg_retries(mut self, retries: u8) -> Self {
+        self.jpeg_retries = retries;
+        self
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    pub fn frame_size(&self) -> FrameSize {
+        self.frame_size
+    }
+
+    pub fn xclk_freq_hz(&self) -> u32 {
+        self.xclk_freq_hz
+    }
+
+    pub fn jpeg_quality(&self) -> u8 {
+        self.jpeg_quality
+    }
+
+    pub fn fb_count(&self) -> usize {
+        self.fb_count
+    }
+
+    pub fn fb_location(&self) -> FbLocation {
+        self.fb_location
+    }
+
+    pub fn grab_mode(&self) -> GrabMode {
+        self.grab_mode
+    }
+
+    pub fn ledc(&self) -> (ledc_timer_t, ledc_channel_t) {
+        (self.ledc_timer, self.ledc_channel)
+    }
+
+    pub fn settle_time(&self) -> Duration {
+        self.settle_time
+    }
+
+    pub fn jpeg_retries(&self) -> u8 {
+        self.jpeg_retries
+    }
+
+    pub fn validate(&self) -> Result<(), CameraConfigError> {
+        if !XCLK_FREQ_RANGE.contains(&self.xclk_freq_hz) {
+            return Err(CameraConfigError::InvalidXclkFreq(self.xclk_freq_hz));
+        }
+        if !JPEG_QUALITY_RANGE.contains(&self.jpeg_quality) {
+            return Err(CameraConfigError::InvalidJpegQuality(self.jpeg_quality));
+        }
+        if self.fb_count == 0 {
+            return Err(CameraConfigError::NoFrameBuffers);
+        }
+        if self.fb_count > 1 && self.fb_location == FbLocation::Dram {
+            return Err(CameraConfigError::MultipleFbsInDram(self.fb_count));
+        }
+        if self.ledc_timer >= esp_idf_sys::ledc_timer_t_LEDC_TIMER_MAX {
+            return Err(CameraConfigError::InvalidLedcTimer(self.ledc_timer));
+        }
+        if self.ledc_channel >= esp_idf_sys::ledc_channel_t_LEDC_CHANNEL_MAX {
+            return Err(CameraConfigError::InvalidLedcChannel(self.ledc_channel));
+        }
+        if self.sccb_i2c_port < 0 {
+            return Err(CameraConfigError::InvalidSccbPort(self.sccb_i2c_port));
+        }
+        Ok(())
+    }
+
+    /// Driver config with every pin left unassigned
+    pub(crate) fn to_raw(&self) -> camera::camera_config_t {
+        camera::camera_config_t {
+            pin_pwdn: -1,
+            pin_reset: -1,
+            pin_xclk: -1,
+
+            xclk_freq_hz: self.xclk_freq_hz as i32,
+            ledc_timer: self.ledc_timer,
+            ledc_channel: self.ledc_channel,
+
+            pixel_format: self.pixel_format.into(),
+            frame_size: self.frame_size.into(),
+
+            jpeg_quality: self.jpeg_quality as i32,
+            fb_count: self.fb_count,
+            grab_mode: self.grab_mode.into(),
+
+            fb_location: self.fb_location.into(),
+
+            sccb_i2c_port: self.sccb_i2c_port,
+
+            ..Default::default()
+        }
+    }
+}
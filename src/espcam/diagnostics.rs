@@ -0,0 +1,59 @@
+use esp_idf_sys::*;
+
+use super::{Board, CameraConfig, CameraError, CameraSensor, FbLocation};
+
+// from esp32-camera's sensor.h
+const ESP_ERR_CAMERA_NOT_DETECTED: esp_err_t = 0x20001;
+const ESP_ERR_CAMERA_NOT_SUPPORTED: esp_err_t = 0x20004;
+
+pub fn psram_available() -> bool {
+    unsafe { heap_caps_get_total_size(MALLOC_CAP_SPIRAM) > 0 }
+}
+
+/// What can be ruled out before starting the driver
+pub(super) fn check_before_init(
+    board: Option<Board>,
+    config: &CameraConfig,
+) -> Result<(), CameraError> {
+    let has_psram = board.is_none_or(|board| board.has_psram()) && psram_available();
+    if config.fb_location() == FbLocation::Psram && !has_psram {
+        return Err(CameraError::PsramMissing);
+    }
+    Ok(())
+}
+
+/// Maps the error of `esp_camera_init` to its likely cause
+pub(super) fn diagnose(err: EspError, config: &CameraConfig) -> CameraError {
+    match err.code() {
+        ESP_ERR_NOT_FOUND | ESP_ERR_CAMERA_NOT_DETECTED => CameraError::NoSensor,
+        ESP_ERR_NOT_SUPPORTED | ESP_ERR_CAMERA_NOT_SUPPORTED => CameraError::UnsupportedSensor,
+        ESP_ERR_NO_MEM => CameraError::FrameBufferAlloc {
+            count: config.fb_count(),
+            location: config.fb_location(),
+        },
+        _ => CameraError::Esp(err),
+    }
+}
+
+/// The driver quietly shrinks frame sizes the sensor can't do, this catches
+/// it once the sensor is known
+pub(super) fn check_after_init(
+    sensor: &CameraSensor,
+    config: &CameraConfig,
+) -> Result<(), CameraError> {
+    let Ok(capabilities) = sensor.capabilities() else {
+        return Ok(());
+    };
+
+    let requested = config.frame_size();
+    match capabilities.max_frame_size {
+        Some(max) if !capabilities.supports_frame_size(requested) => {
+            Err(CameraError::UnsupportedFrameSize {
+                model: capabilities.model,
+                requested,
+                max,
+            })
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::camera;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+    Rgb565,
+    Yuv422,
+    Yuv420,
+    Grayscale,
+    Jpeg,
+    Rgb888,
+    Raw,
+    Rgb444,
+    Rgb555,
+}
+
+#[cfg(target_os = "espidf")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("unknown pixel format {0}")]
+pub struct UnknownPixelFormat(pub camera::pixformat_t);
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 9] = [
+        PixelFormat::Rgb565,
+        PixelFormat::Yuv422,
+        PixelFormat::Yuv420,
+        PixelFormat::Grayscale,
+        PixelFormat::Jpeg,
+        PixelFormat::Rgb888,
+        PixelFormat::Raw,
+        PixelFormat::Rgb444,
+        PixelFormat::Rgb555,
+    ];
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, PixelFormat::Jpeg)
+    }
+
+    /// Storage size of one pixel, `None` for JPEG
+    pub fn bits_per_pixel(&self) -> Option<usize> {
+        match self {
+            PixelFormat::Rgb565 | PixelFormat::Yuv422 => Some(16),
+            PixelFormat::Rgb444 | PixelFormat::Rgb555 => Some(16),
+            PixelFormat::Yuv420 => Some(12),
+            PixelFormat::Grayscale | PixelFormat::Raw => Some(8),
+            PixelFormat::Rgb888 => Some(24),
+            PixelFormat::Jpeg => None,
+        }
+    }
+
+    /// Whole bytes per pixel, `None` for JPEG and for planar YUV420
+    pub fn bytes_per_pixel(&self) -> Option<usize> {
+        self.bits_per_pixel()
+            .filter(|bits| bits % 8 == 0)
+            .map(|bits| bits / 8)
+    }
+
+    /// Expected buffer length of an uncompressed frame
+    pub fn frame_len(&self, width: usize, height: usize) -> Option<usize> {
+        self.bits_per_pixel().map(|bits| width * height * bits / 8)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl TryFrom<camera::pixformat_t> for PixelFormat {
+    type Error = UnknownPixelFormat;
+
+    fn try_from(value: camera::pixformat_t) -> Result<Self, Self::Error> {
+        Ok(match value {
+            camera::pixformat_t_PIXFORMAT_RGB565 => PixelFormat::Rgb565,
+            camera::pixformat_t_PIXFORMAT_YUV422 => PixelFormat::Yuv422,
+            camera::pixformat_t_PIXFORMAT_YUV420 => PixelFormat::Yuv420,
+            camera::pixformat_t_PIXFORMAT_GRAYSCALE => PixelFormat::Grayscale,
+            camera::pixformat_t_PIXFORMAT_JPEG => PixelFormat::Jpeg,
+            camera::pixformat_t_PIXFORMAT_RGB888 => PixelFormat::Rgb888,
+            camera::pixformat_t_PIXFORMAT_RAW => PixelFormat::Raw,
+            camera::pixformat_t_PIXFORMAT_RGB444 => PixelFormat::Rgb444,
+            camera::pixformat_t_PIXFORMAT_RGB555 => PixelFormat::Rgb555,
+            _ => return Err(UnknownPixelFormat(value)),
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<PixelFormat> for camera::pixformat_t {
+    fn from(value: PixelFormat) -> Self {
+        match value {
+            PixelFormat::Rgb565 => camera::pixformat_t_PIXFORMAT_RGB565,
+            PixelFormat::Yuv422 => camera::pixformat_t_PIXFORMAT_YUV422,
+            PixelFormat::Yuv420 => camera::pixformat_t_PIXFORMAT_YUV420,
+            PixelFormat::Grayscale => camera::pixformat_t_PIXFORMAT_GRAYSCALE,
+            PixelFormat::Jpeg => camera::pixformat_t_PIXFORMAT_JPEG,
+            PixelFormat::Rgb888 => camera::pixformat_t_PIXFORMAT_RGB888,
+            PixelFormat::Raw => camera::pixformat_t_PIXFORMAT_RAW,
+            PixelFormat::Rgb444 => camera::pixformat_t_PIXFORMAT_RGB444,
+            PixelFormat::Rgb555 => camera::pixformat_t_PIXFORMAT_RGB555,
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PixelFormat::Rgb565 => "RGB565",
+            PixelFormat::Yuv422 => "YUV422",
+            PixelFormat::Yuv420 => "YUV420",
+            PixelFormat::Grayscale => "GRAYSCALE",
+            PixelFormat::Jpeg => "JPEG",
+            PixelFormat::Rgb888 => "RGB888",
+            PixelFormat::Raw => "RAW",
+            PixelFormat::Rgb444 => "RGB444",
+            PixelFormat::Rgb555 => "RGB555",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum FrameSize {
+    R96x96,
+    Qqvga,
+    R128x128,
+    Qcif,
+    Hqvga,
+    R240x240,
+    Qvga,
+    R320x320,
+    Cif,
+    Hvga,
+    Vga,
+    Svga,
+    Xga,
+    Hd,
+    Sxga,
+    Uxga,
+    Fhd,
+    PHd,
+    P3mp,
+    Qxga,
+    Qhd,
+    Wqxga,
+    PFhd,
+    Qsxga,
+    R5mp,
+}
+
+#[cfg(target_os = "espidf")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("unknown frame size {0}")]
+pub struct UnknownFrameSize(pub camera::framesize_t);
+
+impl FrameSize {
+    pub const ALL: [FrameSize; 25] = [
+        FrameSize::R96x96,
+        FrameSize::Qqvga,
+        FrameSize::R128x128,
+        FrameSize::Qcif,
+        FrameSize::Hqvga,
+        FrameSize::R240x240,
+        FrameSize::Qvga,
+        FrameSize::R320x320,
+        FrameSize::Cif,
+        FrameSize::Hvga,
+        FrameSize::Vga,
+        FrameSize::Svga,
+        FrameSize::Xga,
+        FrameSize::Hd,
+        FrameSize::Sxga,
+        FrameSize::Uxga,
+        FrameSize::Fhd,
+        FrameSize::PHd,
+        FrameSize::P3mp,
+        FrameSize::Qxga,
+        FrameSize::Qhd,
+        FrameSize::Wqxga,
+        FrameSize::PFhd,
+        FrameSize::Qsxga,
+        FrameSize::R5mp,
+    ];
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            FrameSize::R96x96 => (96, 96),
+            FrameSize::Qqvga => (160, 120),
+            FrameSize::R128x128 => (128, 128),
+            FrameSize::Qcif => (176, 144),
+            FrameSize::Hqvga => (240, 176),
+            FrameSize::R240x240 => (240, 240),
+            FrameSize::Qvga => (320, 240),
+            FrameSize::R320x320 => (320, 320),
+            FrameSize::Cif => (400, 296),
+            FrameSize::Hvga => (480, 320),
+            FrameSize::Vga => (640, 480),
+            FrameSize::Svga => (800, 600),
+            FrameSize::Xga => (1024, 768),
+            FrameSize::Hd => (1280, 720),
+            FrameSize::Sxga => (1280, 1024),
+            FrameSize::Uxga => (1600, 1200),
+            FrameSize::Fhd => (1920, 1080),
+            FrameSize::PHd => (720, 1280),
+            FrameSize::P3mp => (864, 1536),
+            FrameSize::Qxga => (2048, 1536),
+            FrameSize::Qhd => (2560, 1440),
+            FrameSize::Wqxga => (2560, 1600),
+            FrameSize::PFhd => (1080, 1920),
+            FrameSize::Qsxga => (2560, 1920),
+            FrameSize::R5mp => (2592, 1944),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.dimensions().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.dimensions().1
+    }
+
+    pub fn from_dimensions(width: usize, height: usize) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|size| size.dimensions() == (width, height))
+    }
+
+    /// Expected buffer length of an uncompressed frame at this size
+    pub fn frame_len(&self, format: PixelFormat) -> Option<usize> {
+        format.frame_len(self.width(), self.height())
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl TryFrom<camera::framesize_t> for FrameSize {
+    type Error = UnknownFrameSize;
+
+    fn try_from(value: camera::framesize_t) -> Result<Self, Self::Error> {
+        Ok(match value {
+            camera::framesize_t_FRAMESIZE_96X96 => FrameSize::R96x96,
+            camera::framesize_t_FRAMESIZE_QQVGA => FrameSize::Qqvga,
+            camera::framesize_t_FRAMESIZE_128X128 => FrameSize::R128x128,
+            camera::framesize_t_FRAMESIZE_QCIF => FrameSize::Qcif,
+            camera::framesize_t_FRAMESIZE_HQVGA => FrameSize::Hqvga,
+            camera::framesize_t_FRAMESIZE_240X240 => FrameSize::R240x240,
+            camera::framesize_t_FRAMESIZE_QVGA => FrameSize::Qvga,
+            camera::framesize_t_FRAMESIZE_320X320 => FrameSize::R320x320,
+            camera::framesize_t_FRAMESIZE_CIF => FrameSize::Cif,
+            camera::framesize_t_FRAMESIZE_HVGA => FrameSize::Hvga,
+            camera::framesize_t_FRAMESIZE_VGA => FrameSize::Vga,
+            camera::framesize_t_FRAMESIZE_SVGA => FrameSize::Svga,
+            camera::framesize_t_FRAMESIZE_XGA => FrameSize::Xga,
+            camera::framesize_t_FRAMESIZE_HD => FrameSize::Hd,
+            camera::framesize_t_FRAMESIZE_SXGA => FrameSize::Sxga,
+            camera::framesize_t_FRAMESIZE_UXGA => FrameSize::Uxga,
+            camera::framesize_t_FRAMESIZE_FHD => FrameSize::Fhd,
+            camera::framesize_t_FRAMESIZE_P_HD => FrameSize::PHd,
+            camera::framesize_t_FRAMESIZE_P_3MP => FrameSize::P3mp,
+            camera::framesize_t_FRAMESIZE_QXGA => FrameSize::Qxga,
+            camera::framesize_t_FRAMESIZE_QHD => FrameSize::Qhd,
+            camera::framesize_t_FRAMESIZE_WQXGA => FrameSize::Wqxga,
+            camera::framesize_t_FRAMESIZE_P_FHD => FrameSize::PFhd,
+            camera::framesize_t_FRAMESIZE_QSXGA => FrameSize::Qsxga,
+            camera::framesize_t_FRAMESIZE_5MP => FrameSize::R5mp,
+            _ => return Err(UnknownFrameSize(value)),
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<FrameSize> for camera::framesize_t {
+    fn from(value: FrameSize) -> Self {
+        match value {
+            FrameSize::R96x96 => camera::framesize_t_FRAMESIZE_96X96,
+            FrameSize::Qqvga => camera::framesize_t_FRAMESIZE_QQVGA,
+            FrameSize::R128x128 => camera::framesize_t_FRAMESIZE_128X128,
+            FrameSize::Qcif => camera::framesize_t_FRAMESIZE_QCIF,
+            FrameSize::Hqvga => camera::framesize_t_FRAMESIZE_HQVGA,
+            FrameSize::R240x240 => camera::framesize_t_FRAMESIZE_240X240,
+            FrameSize::Qvga => camera::framesize_t_FRAMESIZE_QVGA,
+            FrameSize::R320x320 => camera::framesize_t_FRAMESIZE_320X320,
+            FrameSize::Cif => camera::framesize_t_FRAMESIZE_CIF,
+            FrameSize::Hvga => camera::framesize_t_FRAMESIZE_HVGA,
+            FrameSize::Vga => camera::framesize_t_FRAMESIZE_VGA,
+            FrameSize::Svga => camera::framesize_t_FRAMESIZE_SVGA,
+            FrameSize::Xga => camera::framesize_t_FRAMESIZE_XGA,
+            FrameSize::Hd => camera::framesize_t_FRAMESIZE_HD,
+            FrameSize::Sxga => camera::framesize_t_FRAMESIZE_SXGA,
+            FrameSize::Uxga => camera::framesize_t_FRAMESIZE_UXGA,
+            FrameSize::Fhd => camera::framesize_t_FRAMESIZE_FHD,
+            FrameSize::PHd => camera::framesize_t_FRAMESIZE_P_HD,
+            FrameSize::P3mp => camera::framesize_t_FRAMESIZE_P_3MP,
+            FrameSize::Qxga => camera::framesize_t_FRAMESIZE_QXGA,
+            FrameSize::Qhd => camera::framesize_t_FRAMESIZE_QHD,
+            FrameSize::Wqxga => camera::framesize_t_FRAMESIZE_WQXGA,
+            FrameSize::PFhd => camera::framesize_t_FRAMESIZE_P_FHD,
+            FrameSize::Qsxga => camera::framesize_t_FRAMESIZE_QSXGA,
+            FrameSize::R5mp => camera::framesize_t_FRAMESIZE_5MP,
+        }
+    }
+}
+
+impl fmt::Display for FrameSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FrameSize::R96x96 => "96X96",
+            FrameSize::Qqvga => "QQVGA",
+            FrameSize::R128x128 => "128X128",
+            FrameSize::Qcif => "QCIF",
+            FrameSize::Hqvga => "HQVGA",
+            FrameSize::R240x240 => "240X240",
+            FrameSize::Qvga => "QVGA",
+            FrameSize::R320x320 => "320X320",
+            FrameSize::Cif => "CIF",
+            FrameSize::Hvga => "HVGA",
+            FrameSize::Vga => "VGA",
+            FrameSize::Svga => "SVGA",
+            FrameSize::Xga => "XGA",
+            FrameSize::Hd => "HD",
+            FrameSize::Sxga => "SXGA",
+            FrameSize::Uxga => "UXGA",
+            FrameSize::Fhd => "FHD",
+            FrameSize::PHd => "P_HD",
+            FrameSize::P3mp => "P_3MP",
+            FrameSize::Qxga => "QXGA",
+            FrameSize::Qhd => "QHD",
+            FrameSize::Wqxga => "WQXGA",
+            FrameSize::PFhd => "P_FHD",
+            FrameSize::Qsxga => "QSXGA",
+            FrameSize::R5mp => "5MP",
+        };
+        let (width, height) = self.dimensions();
+        write!(f, "{name} ({width}x{height})")
+    }
+}
@@ -0,0 +1,87 @@
+use std::ops::{Deref, DerefMut};
+
+use esp_idf_sys::*;
+
+/// A byte buffer from the C heap, freed with `free()` when dropped
+pub struct HeapBuffer {
+    buf: *mut u8,
+    len: usize,
+}
+
+impl HeapBuffer {
+    /// Takes ownership of memory returned by `malloc` or `heap_caps_malloc`
+    pub(crate) unsafe fn from_raw(buf: *mut u8, len: usize) -> Self {
+        Self { buf, len }
+    }
+
+    /// Allocates zeroed memory in PSRAM, falling back to internal RAM on
+    /// boards without it
+    pub fn psram(len: usize) -> Result<Self, EspError> {
+        Self::alloc(len, true)
+    }
+
+    pub fn copy_from(data: &[u8]) -> Result<Self, EspError> {
+        let buffer = Self::alloc(data.len(), false)?;
+        // no slice over the uninitialized memory before it's written
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buffer.buf, data.len()) };
+        Ok(buffer)
+    }
+
+    /// For C functions writing into the buffer
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.buf
+    }
+
+    fn alloc(len: usize, zeroed: bool) -> Result<Self, EspError> {
+        // malloc(0) may return NULL, which isn't running out of memory
+        let size = len.max(1);
+        let alloc = |caps| unsafe {
+            if zeroed {
+                heap_caps_calloc(1, size, caps)
+            } else {
+                heap_caps_malloc(size, caps)
+            }
+        };
+
+        let mut buf = alloc(MALLOC_CAP_SPIRAM);
+        if buf.is_null() {
+            buf = alloc(MALLOC_CAP_8BIT);
+        }
+        if buf.is_null() {
+            return Err(EspError::from_infallible::<ESP_ERR_NO_MEM>());
+        }
+        Ok(Self {
+            buf: buf as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Deref for HeapBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.buf, self.len) }
+    }
+}
+
+impl DerefMut for HeapBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.buf, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for HeapBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for HeapBuffer {
+    fn drop(&mut self) {
+        unsafe { free(self.buf as *mut _) }
+    }
+}
+
+unsafe impl Send for HeapBuffer {}
+unsafe impl Sync for HeapBuffer {}
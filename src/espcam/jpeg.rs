@@ -0,0 +1,288 @@
+pub const JPEG_QUALITY_RANGE: std::ops::RangeInclusive<u8> = 0..=63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum CorruptJpeg {
+    #[error("no SOI marker at the start")]
+    MissingSoi,
+    #[error("expected a marker at byte {0}")]
+    BadMarker(usize),
+    #[error("segment at byte {0} runs past the end of the data")]
+    Truncated(usize),
+    #[error("no EOI marker after the scan data")]
+    MissingEoi,
+    #[error("EOI before any scan data")]
+    NoScan,
+    #[error("second SOI marker at byte {0}")]
+    UnexpectedSoi(usize),
+    #[error("no frame header before the scan data")]
+    MissingSof,
+    #[error("frame header at byte {0} is malformed")]
+    BadSof(usize),
+}
+
+/// Width and height from the frame header
+pub fn jpeg_size(data: &[u8]) -> Result<(usize, usize), CorruptJpeg> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(CorruptJpeg::MissingSoi);
+    }
+
+    // only segments with a length come before the frame header
+    let mut pos = 2;
+    loop {
+        match data.get(pos) {
+            Some(0xFF) => {}
+            Some(_) => return Err(CorruptJpeg::BadMarker(pos)),
+            None => return Err(CorruptJpeg::MissingSof),
+        }
+        while data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+
+        let (Some(&marker), Some(len)) = (data.get(pos + 1), data.get(pos + 2..pos + 4)) else {
+            return Err(CorruptJpeg::Truncated(pos));
+        };
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        if len < 2 || pos + 2 + len > data.len() {
+            return Err(CorruptJpeg::Truncated(pos));
+        }
+
+        match marker {
+            // SOF0 to SOF15, C4, C8 and CC are DHT, JPG and DAC
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                // precision, then height and width
+                let header = &data[pos + 4..pos + 2 + len];
+                if header.len() < 5 {
+                    return Err(CorruptJpeg::BadSof(pos));
+                }
+                let height = u16::from_be_bytes([header[1], header[2]]) as usize;
+                let width = u16::from_be_bytes([header[3], header[4]]) as usize;
+                // a zero height would come later in a DNL segment
+                if width == 0 || height == 0 {
+                    return Err(CorruptJpeg::BadSof(pos));
+                }
+                return Ok((width, height));
+            }
+            0xD8 => return Err(CorruptJpeg::UnexpectedSoi(pos)),
+            0xDA => return Err(CorruptJpeg::MissingSof),
+            _ => pos += 2 + len,
+        }
+    }
+}
+
+/// Walks the segments of a JPEG and returns its length up to and including
+/// EOI, whatever follows it is garbage.
+pub fn validate_jpeg(data: &[u8]) -> Result<usize, CorruptJpeg> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(CorruptJpeg::MissingSoi);
+    }
+
+    let mut pos = 2;
+    let mut scanned = false;
+
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            return Err(if pos >= data.len() {
+                CorruptJpeg::MissingEoi
+            } else {
+                CorruptJpeg::BadMarker(pos)
+            });
+        }
+        // any number of 0xFF can pad a marker
+        while data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let Some(&marker) = data.get(pos + 1) else {
+            return Err(CorruptJpeg::MissingEoi);
+        };
+
+        match marker {
+            0xD9 if scanned => return Ok(pos + 2),
+            0xD9 => return Err(CorruptJpeg::NoScan),
+            // two frames glued together, or the DMA restarted mid frame
+            0xD8 => return Err(CorruptJpeg::UnexpectedSoi(pos)),
+            // standalone markers, no length
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            _ => {
+                let Some(len) = data.get(pos + 2..pos + 4) else {
+                    return Err(CorruptJpeg::Truncated(pos));
+                };
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                if len < 2 || pos + 2 + len > data.len() {
+                    return Err(CorruptJpeg::Truncated(pos));
+                }
+                pos += 2 + len;
+
+                if marker == 0xDA {
+                    pos = skip_scan(data, pos).ok_or(CorruptJpeg::MissingEoi)?;
+                    scanned = true;
+                }
+            }
+        }
+    }
+}
+
+// entropy coded data ends at the first marker that isn't a stuffed 0xFF00 or a restart
+fn skip_scan(data: &[u8], mut pos: usize) -> Option<usize> {
+    while pos + 1 < data.len() {
+        if data[pos] == 0xFF && !matches!(data[pos + 1], 0x00 | 0xFF | 0xD0..=0xD7) {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOI: [u8; 2] = [0xFF, 0xD8];
+    const EOI: [u8; 2] = [0xFF, 0xD9];
+    // DQT with a 3 byte body
+    const DQT: [u8; 7] = [0xFF, 0xDB, 0x00, 0x05, 0x00, 0x01, 0x02];
+    // SOS header with a 2 byte body, scan data follows
+    const SOS: [u8; 6] = [0xFF, 0xDA, 0x00, 0x04, 0x01, 0x00];
+
+    fn jpeg(scan: &[u8]) -> Vec<u8> {
+        [&SOI[..], &DQT, &SOS, scan, &EOI].concat()
+    }
+
+    #[test]
+    fn valid() {
+        let data = jpeg(&[0x12, 0x34, 0x56]);
+        assert_eq!(validate_jpeg(&data), Ok(data.len()));
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let data = jpeg(&[0x12, 0x34]);
+        let len = data.len();
+        let padded = [data, vec![0x00; 16]].concat();
+        assert_eq!(validate_jpeg(&padded), Ok(len));
+    }
+
+    #[test]
+    fn stuffed_bytes_and_restarts() {
+        // 0xFF00, RST0 and RST7 inside the scan don't end it
+        let data = jpeg(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD7, 0x78]);
+        assert_eq!(validate_jpeg(&data), Ok(data.len()));
+        assert_eq!(
+            skip_scan(&data, SOI.len() + DQT.len() + SOS.len()),
+            Some(data.len() - 2)
+        );
+    }
+
+    #[test]
+    fn padded_marker() {
+        let data = [&SOI[..], &[0xFF], &DQT, &SOS, &[0x12], &[0xFF], &EOI].concat();
+        assert_eq!(validate_jpeg(&data), Ok(data.len()));
+    }
+
+    #[test]
+    fn missing_soi() {
+        assert_eq!(validate_jpeg(&[]), Err(CorruptJpeg::MissingSoi));
+        assert_eq!(
+            validate_jpeg(&jpeg(&[0x12])[2..]),
+            Err(CorruptJpeg::MissingSoi)
+        );
+    }
+
+    #[test]
+    fn missing_eoi() {
+        let data = jpeg(&[0x12, 0x34]);
+        let data = &data[..data.len() - 2];
+        assert_eq!(validate_jpeg(data), Err(CorruptJpeg::MissingEoi));
+        assert_eq!(skip_scan(data, SOI.len() + DQT.len() + SOS.len()), None);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = jpeg(&[0x12, 0x34]);
+        // cut inside the DQT body, inside its length and before its marker
+        assert_eq!(validate_jpeg(&data[..7]), Err(CorruptJpeg::Truncated(2)));
+        assert_eq!(validate_jpeg(&data[..5]), Err(CorruptJpeg::Truncated(2)));
+        assert_eq!(validate_jpeg(&data[..3]), Err(CorruptJpeg::MissingEoi));
+    }
+
+    #[test]
+    fn bad_segment_length() {
+        let mut data = jpeg(&[0x12]);
+        data[4..6].copy_from_slice(&[0x00, 0x01]);
+        assert_eq!(validate_jpeg(&data), Err(CorruptJpeg::Truncated(2)));
+
+        data[4..6].copy_from_slice(&[0x7F, 0xFF]);
+        assert_eq!(validate_jpeg(&data), Err(CorruptJpeg::Truncated(2)));
+    }
+
+    #[test]
+    fn bad_marker() {
+        let data = [&SOI[..], &[0x00, 0xDB], &DQT[2..], &SOS, &EOI].concat();
+        assert_eq!(validate_jpeg(&data), Err(CorruptJpeg::BadMarker(2)));
+    }
+
+    #[test]
+    fn no_scan() {
+        let data = [&SOI[..], &DQT, &EOI].concat();
+        assert_eq!(validate_jpeg(&data), Err(CorruptJpeg::NoScan));
+    }
+
+    #[test]
+    fn size() {
+        // SOF0: precision 8, 480 rows of 640 pixels, one component
+        let sof = [
+            0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x01, 0x01, 0x11, 0x00,
+        ];
+        let data = [&SOI[..], &DQT, &[0xFF, 0xC4, 0x00, 0x02], &sof, &SOS, &EOI].concat();
+        assert_eq!(jpeg_size(&data), Ok((640, 480)));
+
+        let mut zero = data.clone();
+        zero[18..20].copy_from_slice(&[0, 0]);
+        assert_eq!(jpeg_size(&zero), Err(CorruptJpeg::BadSof(13)));
+
+        assert_eq!(jpeg_size(&data[..15]), Err(CorruptJpeg::Truncated(13)));
+        assert_eq!(jpeg_size(&jpeg(&[0x12])), Err(CorruptJpeg::MissingSof));
+        assert_eq!(jpeg_size(&data[2..]), Err(CorruptJpeg::MissingSoi));
+    }
+
+    #[test]
+    fn second_soi() {
+        let data = [&SOI[..], &DQT, &SOI, &DQT, &SOS, &[0x12], &EOI].concat();
+        assert_eq!(validate_jpeg(&data), Err(CorruptJpeg::UnexpectedSoi(9)));
+    }
+
+    // a complete 160x120 frame, and the ways the DMA buffer goes wrong with it,
+    // see tests/fixtures/README.md
+    const FRAME: &[u8] = include_bytes!("../../tests/fixtures/frame.jpg");
+    const TRAILING_GARBAGE: &[u8] = include_bytes!("../../tests/fixtures/trailing_garbage.jpg");
+    const TRUNCATED: &[u8] = include_bytes!("../../tests/fixtures/truncated.jpg");
+    const SECOND_SOI: &[u8] = include_bytes!("../../tests/fixtures/second_soi.jpg");
+
+    #[test]
+    fn fixture_frame() {
+        assert_eq!(validate_jpeg(FRAME), Ok(FRAME.len()));
+        assert_eq!(jpeg_size(FRAME), Ok((160, 120)));
+    }
+
+    #[test]
+    fn fixture_trailing_garbage() {
+        assert_eq!(validate_jpeg(TRAILING_GARBAGE), Ok(FRAME.len()));
+        assert_eq!(jpeg_size(TRAILING_GARBAGE), Ok((160, 120)));
+    }
+
+    #[test]
+    fn fixture_truncated() {
+        assert_eq!(validate_jpeg(TRUNCATED), Err(CorruptJpeg::MissingEoi));
+        // the headers made it, the size is still known
+        assert_eq!(jpeg_size(TRUNCATED), Ok((160, 120)));
+    }
+
+    #[test]
+    fn fixture_second_soi() {
+        // the scan of the first frame is cut off by the start of the next one
+        assert_eq!(
+            validate_jpeg(SECOND_SOI),
+            Err(CorruptJpeg::UnexpectedSoi(1800))
+        );
+        assert_eq!(jpeg_size(SECOND_SOI), Ok((160, 120)));
+    }
+}
@@ -0,0 +1,369 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use super::{
+    check_decode_memory, edit_jpeg, FrameSize, JpegBuffer, JpegError, PixelFormat,
+    JPEG_ENCODE_QUALITY_RANGE,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MaskError {
+    #[error("{0} frames can't be masked")]
+    UnsupportedFormat(PixelFormat),
+    #[error("frame buffer holds {actual} bytes, {expected} expected")]
+    BufferTooShort { expected: usize, actual: usize },
+    #[error("jpeg decoding or encoding failed: {0}")]
+    Jpeg(#[from] JpegError),
+    #[error("jpeg quality {0} is out of range 1..=100")]
+    InvalidQuality(u8),
+}
+
+/// A region in normalised coordinates, from 0.0 to 1.0 across the frame, so
+/// it covers the same part of the scene at every frame size
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum MaskRegion {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    /// Closed from the last point back to the first, self-intersecting
+    /// polygons are filled even-odd
+    Polygon { points: Vec<(f32, f32)> },
+}
+
+impl MaskRegion {
+    /// Appends the `[start, end)` pixel spans covered on row `y`. Pixels the
+    /// edge only partly covers are masked too.
+    fn spans(&self, y: usize, width: usize, height: usize, spans: &mut Vec<(usize, usize)>) {
+        let (w, h) = (width as f32, height as f32);
+
+        match self {
+            MaskRegion::Rect {
+                x: left,
+                y: top,
+                width: rect_width,
+                height: rect_height,
+            } => {
+                let row_start = (top * h).floor();
+                let row_end = ((top + rect_height) * h).ceil();
+                if (y as f32) < row_start || (y as f32) >= row_end {
+                    return;
+                }
+                spans.push((
+                    to_column(left * w, width),
+                    to_column(((left + rect_width) * w).ceil(), width),
+                ));
+            }
+            MaskRegion::Polygon { points } => {
+                // crossings of the row center with every edge
+                let row = y as f32 + 0.5;
+                let mut crossings: Vec<f32> = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .filter_map(|(&(x0, y0), &(x1, y1))| {
+                        let (x0, y0, x1, y1) = (x0 * w, y0 * h, x1 * w, y1 * h);
+                        ((y0 <= row) != (y1 <= row))
+                            .then(|| x0 + (row - y0) / (y1 - y0) * (x1 - x0))
+                    })
+                    .collect();
+                crossings.sort_by(f32::total_cmp);
+
+                for pair in crossings.chunks_exact(2) {
+                    spans.push((to_column(pair[0], width), to_column(pair[1].ceil(), width)));
+                }
+            }
+        }
+    }
+}
+
+fn to_column(x: f32, width: usize) -> usize {
+    (x.floor().max(0.0) as usize).min(width)
+}
+
+/// Regions blacked out of every frame before it leaves the camera, see
+/// [`Camera::set_privacy_mask`](super::Camera::set_privacy_mask).
+///
+/// ```ignore
+/// let mask = PrivacyMask::new()
+///     .rect(0.7, 0.0, 0.3, 0.4)
+///     .polygon([(0.0, 0.6), (0.3, 0.5), (0.3, 1.0), (0.0, 1.0)]);
+/// camera.set_privacy_mask(mask)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyMask {
+    regions: Vec<MaskRegion>,
+    #[serde(deserialize_with = "jpeg_quality")]
+    jpeg_quality: u8,
+}
+
+// stored masks go through the same check as the builder
+fn jpeg_quality<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let quality = u8::deserialize(deserializer)?;
+    if !JPEG_ENCODE_QUALITY_RANGE.contains(&quality) {
+        return Err(de::Error::custom(MaskError::InvalidQuality(quality)));
+    }
+    Ok(quality)
+}
+
+impl Default for PrivacyMask {
+    fn default() -> Self {
+        Self {
+            regions: Vec::new(),
+            jpeg_quality: 80,
+        }
+    }
+}
+
+impl PrivacyMask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn rect(mut self, x: f32, y: f32, width: f32, height: f32) -> Self {
+        self.push(MaskRegion::Rect {
+            x,
+            y,
+            width,
+            height,
+        });
+        self
+    }
+
+    pub fn polygon(mut self, points: impl IntoIterator<Item = (f32, f32)>) -> Self {
+        self.push(MaskRegion::Polygon {
+            points: points.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Quality masked JPEG frames are encoded with again, from 1 to 100 like
+    /// [`encode_jpeg`](super::encode_jpeg)
+    pub fn with_jpeg_quality(mut self, quality: u8) -> Result<Self, MaskError> {
+        if !JPEG_ENCODE_QUALITY_RANGE.contains(&quality) {
+            return Err(MaskError::InvalidQuality(quality));
+        }
+        self.jpeg_quality = quality;
+        Ok(self)
+    }
+
+    pub fn jpeg_quality(&self) -> u8 {
+        self.jpeg_quality
+    }
+
+    pub fn push(&mut self, region: MaskRegion) {
+        self.regions.push(region);
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn regions(&self) -> &[MaskRegion] {
+        &self.regions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Checks that `format` frames at `frame_size` can be masked. JPEG ones
+    /// need a block for the decoded frame, see [`apply_jpeg`](Self::apply_jpeg),
+    /// raw ones whole bytes per pixel, see [`apply_raw`](Self::apply_raw).
+    pub fn check_memory(
+        &self,
+        format: PixelFormat,
+        frame_size: FrameSize,
+    ) -> Result<(), MaskError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        match format {
+            PixelFormat::Jpeg => {
+                let (width, height) = frame_size.dimensions();
+                Ok(check_decode_memory(width, height)?)
+            }
+            // every frame would be dropped
+            format if format.bytes_per_pixel().is_none() => {
+                Err(MaskError::UnsupportedFormat(format))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Blacks out the regions in place. Works on every format with whole
+    /// bytes per pixel, not on JPEG or planar YUV420.
+    pub fn apply_raw(
+        &self,
+        data: &mut [u8],
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Result<(), MaskError> {
+        let bytes_per_pixel = format
+            .bytes_per_pixel()
+            .ok_or(MaskError::UnsupportedFormat(format))?;
+        let expected = width * height * bytes_per_pixel;
+        if data.len() < expected {
+            return Err(MaskError::BufferTooShort {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        let mut spans = Vec::new();
+        for y in 0..height {
+            spans.clear();
+            for region in &self.regions {
+                region.spans(y, width, height, &mut spans);
+            }
+
+            let row = y * width;
+            for &(start, end) in spans.iter().filter(|(start, end)| start < end) {
+                let pixels =
+                    &mut data[(row + start) * bytes_per_pixel..(row + end) * bytes_per_pixel];
+                if format == PixelFormat::Yuv422 {
+                    // black is no luma and neutral chroma, the pixel next to
+                    // the edge loses its color too
+                    for pair in pixels.chunks_exact_mut(2) {
+                        pair.copy_from_slice(&[0, 128]);
+                    }
+                } else {
+                    pixels.fill(0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the JPEG, masks it and encodes it again with [`edit_jpeg`].
+    /// Needs 3 bytes per pixel of free memory for the decoded frame.
+    pub fn apply_jpeg(&self, jpeg: &[u8]) -> Result<JpegBuffer, MaskError> {
+        edit_jpeg(jpeg, self.jpeg_quality, |data, width, height| {
+            self.apply_raw(data, width, height, PixelFormat::Rgb888)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpeg_quality_range() {
+        assert_eq!(PrivacyMask::new().jpeg_quality(), 80);
+        assert_eq!(
+            PrivacyMask::new()
+                .with_jpeg_quality(100)
+                .unwrap()
+                .jpeg_quality(),
+            100
+        );
+        assert!(matches!(
+            PrivacyMask::new().with_jpeg_quality(0),
+            Err(MaskError::InvalidQuality(0))
+        ));
+        assert!(matches!(
+            PrivacyMask::new().with_jpeg_quality(101),
+            Err(MaskError::InvalidQuality(101))
+        ));
+    }
+
+    #[test]
+    fn from_json() {
+        let mask = PrivacyMask::from_json(r#"{"regions":[]}"#).unwrap();
+        assert_eq!(mask.jpeg_quality(), 80);
+
+        let json = serde_json::to_string(&PrivacyMask::new().rect(0.0, 0.0, 0.5, 0.5)).unwrap();
+        let mask = PrivacyMask::from_json(&json).unwrap();
+        assert_eq!(mask.regions().len(), 1);
+
+        assert!(PrivacyMask::from_json(r#"{"jpeg_quality":0}"#).is_err());
+        assert!(PrivacyMask::from_json(r#"{"jpeg_quality":101}"#).is_err());
+    }
+
+    #[test]
+    fn rect() {
+        let mut data = vec![1; 4 * 4];
+        let mask = PrivacyMask::new().rect(0.5, 0.25, 0.5, 0.5);
+        mask.apply_raw(&mut data, 4, 4, PixelFormat::Grayscale)
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            1, 1, 1, 1,
+            1, 1, 0, 0,
+            1, 1, 0, 0,
+            1, 1, 1, 1,
+        ]);
+    }
+
+    #[test]
+    fn polygon() {
+        let mut data = vec![1; 4 * 4];
+        // lower left triangle
+        let mask = PrivacyMask::new().polygon([(0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        mask.apply_raw(&mut data, 4, 4, PixelFormat::Grayscale)
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0, 1, 1, 1,
+            0, 0, 1, 1,
+            0, 0, 0, 1,
+            0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn yuv422_black() {
+        let mut data = vec![200; 2 * 2 * 2];
+        let mask = PrivacyMask::new().rect(0.0, 0.0, 1.0, 0.5);
+        mask.apply_raw(&mut data, 2, 2, PixelFormat::Yuv422)
+            .unwrap();
+        assert_eq!(data, [0, 128, 0, 128, 200, 200, 200, 200]);
+    }
+
+    #[test]
+    fn short_buffer() {
+        let mask = PrivacyMask::new().rect(0.0, 0.0, 1.0, 1.0);
+        assert!(matches!(
+            mask.apply_raw(&mut [0; 5], 2, 2, PixelFormat::Rgb565),
+            Err(MaskError::BufferTooShort {
+                expected: 8,
+                actual: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn check_memory_without_decoding() {
+        let empty = PrivacyMask::new();
+        assert!(empty
+            .check_memory(PixelFormat::Jpeg, FrameSize::Uxga)
+            .is_ok());
+
+        let mask = PrivacyMask::new().rect(0.0, 0.0, 1.0, 1.0);
+        assert!(mask
+            .check_memory(PixelFormat::Rgb565, FrameSize::Uxga)
+            .is_ok());
+    }
+
+    #[test]
+    fn check_memory_unsupported_format() {
+        let mask = PrivacyMask::new().rect(0.0, 0.0, 1.0, 1.0);
+        assert!(matches!(
+            mask.check_memory(PixelFormat::Yuv420, FrameSize::Qvga),
+            Err(MaskError::UnsupportedFormat(PixelFormat::Yuv420))
+        ));
+        assert!(PrivacyMask::new()
+            .check_memory(PixelFormat::Yuv420, FrameSize::Qvga)
+            .is_ok());
+    }
+}
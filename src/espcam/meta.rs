@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use esp_idf_sys::camera;
+
+use super::{uptime, wall_clock_synced};
+
+const STATS_WINDOW: usize = 32;
+
+/// When a frame was captured and where it sits among the frames handed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameMeta {
+    /// Capture time since boot
+    pub timestamp: Duration,
+    /// Counts up from 1 for every frame taken from the driver
+    pub sequence: u64,
+    /// Sensor frames estimated to have gone by since the previous one
+    pub dropped: u32,
+}
+
+impl FrameMeta {
+    /// Capture time on the wall clock, `None` until the time is synced
+    pub fn wall_time(&self) -> Option<SystemTime> {
+        if !wall_clock_synced() {
+            return None;
+        }
+        SystemTime::now().checked_sub(uptime().saturating_sub(self.timestamp))
+    }
+}
+
+/// Rolling statistics over the last frames taken from the driver
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    pub fps: f32,
+    pub mean_size: usize,
+    pub min_size: usize,
+    pub max_size: usize,
+    /// Since the camera was started
+    pub frames: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct FrameCounter {
+    sequence: u64,
+    dropped: u64,
+    last_timestamp: Option<Duration>,
+    // the shortest gap seen is taken as the sensor frame period
+    frame_period: Option<Duration>,
+    window: VecDeque<(Duration, usize)>,
+}
+
+impl FrameCounter {
+    pub(super) fn next(&mut self, timestamp: Duration, size: usize) -> FrameMeta {
+        self.sequence += 1;
+
+        let mut dropped = 0;
+        if let Some(last) = self.last_timestamp {
+            let gap = timestamp.saturating_sub(last);
+            if !gap.is_zero() {
+                let period = self.frame_period.map_or(gap, |period| period.min(gap));
+                self.frame_period = Some(period);
+                dropped = (gap.as_secs_f32() / period.as_secs_f32()).round() as u32;
+                dropped = dropped.saturating_sub(1);
+            }
+        }
+        self.dropped += dropped as u64;
+        self.last_timestamp = Some(timestamp);
+
+        if self.window.len() == STATS_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back((timestamp, size));
+
+        FrameMeta {
+            timestamp,
+            sequence: self.sequence,
+            dropped,
+        }
+    }
+
+    pub(super) fn stats(&self) -> FrameStats {
+        let sizes = self.window.iter().map(|(_, size)| *size);
+        let fps = match (self.window.front(), self.window.back()) {
+            (Some((first, _)), Some((last, _))) if last > first => {
+                (self.window.len() - 1) as f32 / (*last - *first).as_secs_f32()
+            }
+            _ => 0.0,
+        };
+
+        FrameStats {
+            fps,
+            mean_size: sizes.clone().sum::<usize>() / self.window.len().max(1),
+            min_size: sizes.clone().min().unwrap_or(0),
+            max_size: sizes.max().unwrap_or(0),
+            frames: self.sequence,
+            dropped: self.dropped,
+        }
+    }
+}
+
+pub(super) fn timeval_to_duration(tv: camera::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
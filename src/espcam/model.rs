@@ -0,0 +1,164 @@
+use std::ops::RangeInclusive;
+
+use super::FrameSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorControl {
+    InitStatus,
+    Reset,
+    Pixformat,
+    Framesize,
+    Contrast,
+    Brightness,
+    Saturation,
+    Sharpness,
+    Denoise,
+    Gainceiling,
+    Quality,
+    Colorbar,
+    Whitebal,
+    GainCtrl,
+    ExposureCtrl,
+    Hmirror,
+    Vflip,
+    Aec2,
+    AwbGain,
+    AgcGain,
+    AecValue,
+    SpecialEffect,
+    WbMode,
+    AeLevel,
+    Dcw,
+    Bpc,
+    Wpc,
+    RawGma,
+    Lenc,
+    GetReg,
+    SetReg,
+    ResRaw,
+    Pll,
+    Xclk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorModel {
+    Ov2640,
+    Ov3660,
+    Ov5640,
+    Ov7670,
+    Ov7725,
+    Ov9650,
+    Nt99141,
+    Gc0308,
+    Gc032a,
+    Gc2145,
+    Bf3005,
+    Bf20a6,
+    Sc101iot,
+    Sc030iot,
+    Sc031gs,
+    Unknown(u16),
+}
+
+impl SensorModel {
+    pub fn from_pid(pid: u16) -> Self {
+        match pid {
+            0x26 => SensorModel::Ov2640,
+            0x3660 => SensorModel::Ov3660,
+            0x5640 => SensorModel::Ov5640,
+            0x76 => SensorModel::Ov7670,
+            0x77 => SensorModel::Ov7725,
+            0x96 => SensorModel::Ov9650,
+            0x1410 => SensorModel::Nt99141,
+            0x9b => SensorModel::Gc0308,
+            0x232a => SensorModel::Gc032a,
+            0x2145 => SensorModel::Gc2145,
+            0x30 => SensorModel::Bf3005,
+            0x20a6 => SensorModel::Bf20a6,
+            0xda4a => SensorModel::Sc101iot,
+            0x9a46 => SensorModel::Sc030iot,
+            0x0031 => SensorModel::Sc031gs,
+            pid => SensorModel::Unknown(pid),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SensorModel::Ov2640 => "OV2640",
+            SensorModel::Ov3660 => "OV3660",
+            SensorModel::Ov5640 => "OV5640",
+            SensorModel::Ov7670 => "OV7670",
+            SensorModel::Ov7725 => "OV7725",
+            SensorModel::Ov9650 => "OV9650",
+            SensorModel::Nt99141 => "NT99141",
+            SensorModel::Gc0308 => "GC0308",
+            SensorModel::Gc032a => "GC032A",
+            SensorModel::Gc2145 => "GC2145",
+            SensorModel::Bf3005 => "BF3005",
+            SensorModel::Bf20a6 => "BF20A6",
+            SensorModel::Sc101iot => "SC101IOT",
+            SensorModel::Sc030iot => "SC030IOT",
+            SensorModel::Sc031gs => "SC031GS",
+            SensorModel::Unknown(_) => "unknown",
+        }
+    }
+
+    /// Values accepted by the driver for a control that takes a level
+    pub fn range(&self, control: SensorControl) -> Option<RangeInclusive<i32>> {
+        let omnivision_3mp = matches!(self, SensorModel::Ov3660 | SensorModel::Ov5640);
+
+        Some(match control {
+            SensorControl::Brightness | SensorControl::Contrast | SensorControl::Sharpness
+                if omnivision_3mp =>
+            {
+                -3..=3
+            }
+            SensorControl::Saturation if omnivision_3mp => -4..=4,
+            SensorControl::Brightness
+            | SensorControl::Contrast
+            | SensorControl::Saturation
+            | SensorControl::Sharpness => -2..=2,
+            SensorControl::AeLevel if omnivision_3mp => -5..=5,
+            SensorControl::AeLevel => -2..=2,
+            SensorControl::AgcGain if omnivision_3mp => 0..=64,
+            SensorControl::AgcGain => 0..=30,
+            SensorControl::AecValue if omnivision_3mp => 0..=1536,
+            SensorControl::AecValue => 0..=1200,
+            SensorControl::Denoise => 0..=8,
+            SensorControl::Gainceiling => 0..=6,
+            SensorControl::Quality => 0..=63,
+            SensorControl::SpecialEffect => 0..=6,
+            SensorControl::WbMode => 0..=4,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SensorCapabilities {
+    pub model: SensorModel,
+    /// `None` when the driver doesn't know the sensor
+    pub max_frame_size: Option<FrameSize>,
+    pub jpeg: bool,
+    pub controls: Vec<SensorControl>,
+}
+
+impl SensorCapabilities {
+    pub fn supports(&self, control: SensorControl) -> bool {
+        self.controls.contains(&control)
+    }
+
+    pub fn supports_frame_size(&self, frame_size: FrameSize) -> bool {
+        self.max_frame_size.is_some_and(|max| {
+            frame_size.width() <= max.width() && frame_size.height() <= max.height()
+        })
+    }
+
+    pub fn range(&self, control: SensorControl) -> Option<RangeInclusive<i32>> {
+        if self.supports(control) {
+            self.model.range(control)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, SystemTime};
+
+use super::{encode_jpeg, Frame, FrameMeta, HeapBuffer, JpegBuffer, JpegError, PixelFormat};
+
+/// A copy of a frame that doesn't hold on to the driver's buffer, so capture
+/// can go on while it's being uploaded or processed
+pub struct OwnedFrame {
+    data: HeapBuffer,
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    meta: FrameMeta,
+}
+
+impl OwnedFrame {
+    pub(crate) fn new(
+        data: HeapBuffer,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+        meta: FrameMeta,
+    ) -> Self {
+        Self {
+            data,
+            width,
+            height,
+            format,
+            meta,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Capture time since boot
+    pub fn timestamp(&self) -> Duration {
+        self.meta.timestamp
+    }
+
+    pub fn wall_time(&self) -> Option<SystemTime> {
+        self.meta.wall_time()
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.meta.sequence
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.meta.dropped
+    }
+
+    pub fn meta(&self) -> &FrameMeta {
+        &self.meta
+    }
+
+    pub fn to_jpeg(&self, quality: u8) -> Result<JpegBuffer, JpegError> {
+        encode_jpeg(self, quality)
+    }
+
+    pub fn into_data(self) -> HeapBuffer {
+        self.data
+    }
+}
+
+impl Frame for OwnedFrame {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn format(&self) -> PixelFormat {
+        self.format
+    }
+}
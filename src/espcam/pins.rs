@@ -0,0 +1,209 @@
+use esp_idf_hal::gpio::{AnyIOPin, AnyInputPin, AnyOutputPin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Board {
+    AiThinker,
+    /// The M5Stack camera with PSRAM
+    M5StackCamera,
+    /// The M5Stack ESP32CAM, without PSRAM
+    M5StackEsp32Cam,
+    EspEye,
+    WroverKit,
+    TtgoTCamera,
+}
+
+impl Board {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Board::AiThinker => "AI-Thinker ESP32-CAM",
+            Board::M5StackCamera => "M5Stack Camera",
+            Board::M5StackEsp32Cam => "M5Stack ESP32CAM",
+            Board::EspEye => "ESP-EYE",
+            Board::WroverKit => "ESP-WROVER-KIT",
+            Board::TtgoTCamera => "TTGO T-Camera",
+        }
+    }
+
+    pub fn has_pwdn(&self) -> bool {
+        matches!(self, Board::AiThinker | Board::TtgoTCamera)
+    }
+
+    pub fn has_psram(&self) -> bool {
+        !matches!(self, Board::M5StackEsp32Cam)
+    }
+}
+
+/// Wiring between the ESP32 and the camera module.
+///
+/// Use [`camera_pins!`](crate::camera_pins) for the known boards. When `sda`
+/// and `scl` are `None` the driver talks to the sensor over the I2C port set
+/// with [`CameraConfig::with_sccb_i2c_port`](super::CameraConfig::with_sccb_i2c_port).
+pub struct CameraPins<'d> {
+    pub board: Option<Board>,
+
+    pub pwdn: Option<AnyOutputPin<'d>>,
+    pub reset: Option<AnyOutputPin<'d>>,
+    pub xclk: AnyOutputPin<'d>,
+
+    pub sda: Option<AnyIOPin<'d>>,
+    pub scl: Option<AnyIOPin<'d>>,
+
+    pub d0: AnyInputPin<'d>,
+    pub d1: AnyInputPin<'d>,
+    pub d2: AnyInputPin<'d>,
+    pub d3: AnyInputPin<'d>,
+    pub d4: AnyInputPin<'d>,
+    pub d5: AnyInputPin<'d>,
+    pub d6: AnyInputPin<'d>,
+    pub d7: AnyInputPin<'d>,
+    pub vsync: AnyInputPin<'d>,
+    pub href: AnyInputPin<'d>,
+    pub pclk: AnyInputPin<'d>,
+
+    /// The white illumination LED, not used by the camera; take it for
+    /// [`Flash`](crate::flash::Flash) before handing the pins over
+    pub flash_led: Option<AnyOutputPin<'d>>,
+}
+
+/// Builds the [`CameraPins`](crate::espcam::CameraPins) of a known board out of
+/// `peripherals.pins`, leaving the other GPIOs available:
+///
+/// ```ignore
+/// let mut pins = espcam::camera_pins!(AiThinker, peripherals.pins);
+/// let flash_led = pins.flash_led.take();
+/// ```
+#[macro_export]
+macro_rules! camera_pins {
+    (AiThinker, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::AiThinker),
+            pwdn: Some($pins.gpio32.into()),
+            reset: None,
+            xclk: $pins.gpio0.into(),
+            sda: Some($pins.gpio26.into()),
+            scl: Some($pins.gpio27.into()),
+            d0: $pins.gpio5.into(),
+            d1: $pins.gpio18.into(),
+            d2: $pins.gpio19.into(),
+            d3: $pins.gpio21.into(),
+            d4: $pins.gpio36.into(),
+            d5: $pins.gpio39.into(),
+            d6: $pins.gpio34.into(),
+            d7: $pins.gpio35.into(),
+            vsync: $pins.gpio25.into(),
+            href: $pins.gpio23.into(),
+            pclk: $pins.gpio22.into(),
+            flash_led: Some($pins.gpio4.into()),
+        }
+    };
+    (M5StackCamera, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::M5StackCamera),
+            pwdn: None,
+            reset: Some($pins.gpio15.into()),
+            xclk: $pins.gpio27.into(),
+            sda: Some($pins.gpio25.into()),
+            scl: Some($pins.gpio23.into()),
+            d0: $pins.gpio32.into(),
+            d1: $pins.gpio35.into(),
+            d2: $pins.gpio34.into(),
+            d3: $pins.gpio5.into(),
+            d4: $pins.gpio39.into(),
+            d5: $pins.gpio18.into(),
+            d6: $pins.gpio36.into(),
+            d7: $pins.gpio19.into(),
+            vsync: $pins.gpio22.into(),
+            href: $pins.gpio26.into(),
+            pclk: $pins.gpio21.into(),
+            flash_led: None,
+        }
+    };
+    (M5StackEsp32Cam, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::M5StackEsp32Cam),
+            pwdn: None,
+            reset: Some($pins.gpio15.into()),
+            xclk: $pins.gpio27.into(),
+            sda: Some($pins.gpio25.into()),
+            scl: Some($pins.gpio23.into()),
+            d0: $pins.gpio17.into(),
+            d1: $pins.gpio35.into(),
+            d2: $pins.gpio34.into(),
+            d3: $pins.gpio5.into(),
+            d4: $pins.gpio39.into(),
+            d5: $pins.gpio18.into(),
+            d6: $pins.gpio36.into(),
+            d7: $pins.gpio19.into(),
+            vsync: $pins.gpio22.into(),
+            href: $pins.gpio26.into(),
+            pclk: $pins.gpio21.into(),
+            flash_led: None,
+        }
+    };
+    (EspEye, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::EspEye),
+            pwdn: None,
+            reset: None,
+            xclk: $pins.gpio4.into(),
+            sda: Some($pins.gpio18.into()),
+            scl: Some($pins.gpio23.into()),
+            d0: $pins.gpio34.into(),
+            d1: $pins.gpio13.into(),
+            d2: $pins.gpio14.into(),
+            d3: $pins.gpio35.into(),
+            d4: $pins.gpio39.into(),
+            d5: $pins.gpio38.into(),
+            d6: $pins.gpio37.into(),
+            d7: $pins.gpio36.into(),
+            vsync: $pins.gpio5.into(),
+            href: $pins.gpio27.into(),
+            pclk: $pins.gpio25.into(),
+            flash_led: None,
+        }
+    };
+    (WroverKit, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::WroverKit),
+            pwdn: None,
+            reset: None,
+            xclk: $pins.gpio21.into(),
+            sda: Some($pins.gpio26.into()),
+            scl: Some($pins.gpio27.into()),
+            d0: $pins.gpio4.into(),
+            d1: $pins.gpio5.into(),
+            d2: $pins.gpio18.into(),
+            d3: $pins.gpio19.into(),
+            d4: $pins.gpio36.into(),
+            d5: $pins.gpio39.into(),
+            d6: $pins.gpio34.into(),
+            d7: $pins.gpio35.into(),
+            vsync: $pins.gpio25.into(),
+            href: $pins.gpio23.into(),
+            pclk: $pins.gpio22.into(),
+            flash_led: None,
+        }
+    };
+    (TtgoTCamera, $pins:expr) => {
+        $crate::espcam::CameraPins {
+            board: Some($crate::espcam::Board::TtgoTCamera),
+            pwdn: Some($pins.gpio26.into()),
+            reset: None,
+            xclk: $pins.gpio32.into(),
+            sda: Some($pins.gpio13.into()),
+            scl: Some($pins.gpio12.into()),
+            d0: $pins.gpio5.into(),
+            d1: $pins.gpio14.into(),
+            d2: $pins.gpio4.into(),
+            d3: $pins.gpio15.into(),
+            d4: $pins.gpio18.into(),
+            d5: $pins.gpio23.into(),
+            d6: $pins.gpio36.into(),
+            d7: $pins.gpio39.into(),
+            vsync: $pins.gpio27.into(),
+            href: $pins.gpio25.into(),
+            pclk: $pins.gpio19.into(),
+            flash_led: None,
+        }
+    };
+}
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CameraSensor, FrameSize, GainCeiling, PixelFormat, SensorControl, SensorError};
+
+/// A single sensor control and the value to set it to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "control", content = "value", rename_all = "snake_case")]
+pub enum SensorSetting {
+    Pixformat(PixelFormat),
+    Framesize(FrameSize),
+    /// Sensor clock in MHz, on the LEDC timer the camera was configured with
+    XclkMhz(i32),
+    Quality(i32),
+    Brightness(i32),
+    Contrast(i32),
+    Saturation(i32),
+    Sharpness(i32),
+    Denoise(i32),
+    SpecialEffect(i32),
+    Whitebal(bool),
+    AwbGain(bool),
+    WbMode(i32),
+    ExposureCtrl(bool),
+    Aec2(bool),
+    AeLevel(i32),
+    AecValue(i32),
+    GainCtrl(bool),
+    AgcGain(i32),
+    Gainceiling(GainCeiling),
+    Bpc(bool),
+    Wpc(bool),
+    RawGma(bool),
+    Lenc(bool),
+    Hmirror(bool),
+    Vflip(bool),
+    Dcw(bool),
+    Colorbar(bool),
+}
+
+impl SensorSetting {
+    pub fn control(&self) -> SensorControl {
+        match self {
+            SensorSetting::Pixformat(_) => SensorControl::Pixformat,
+            SensorSetting::Framesize(_) => SensorControl::Framesize,
+            SensorSetting::XclkMhz(_) => SensorControl::Xclk,
+            SensorSetting::Quality(_) => SensorControl::Quality,
+            SensorSetting::Brightness(_) => SensorControl::Brightness,
+            SensorSetting::Contrast(_) => SensorControl::Contrast,
+            SensorSetting::Saturation(_) => SensorControl::Saturation,
+            SensorSetting::Sharpness(_) => SensorControl::Sharpness,
+            SensorSetting::Denoise(_) => SensorControl::Denoise,
+            SensorSetting::SpecialEffect(_) => SensorControl::SpecialEffect,
+            SensorSetting::Whitebal(_) => SensorControl::Whitebal,
+            SensorSetting::AwbGain(_) => SensorControl::AwbGain,
+            SensorSetting::WbMode(_) => SensorControl::WbMode,
+            SensorSetting::ExposureCtrl(_) => SensorControl::ExposureCtrl,
+            SensorSetting::Aec2(_) => SensorControl::Aec2,
+            SensorSetting::AeLevel(_) => SensorControl::AeLevel,
+            SensorSetting::AecValue(_) => SensorControl::AecValue,
+            SensorSetting::GainCtrl(_) => SensorControl::GainCtrl,
+            SensorSetting::AgcGain(_) => SensorControl::AgcGain,
+            SensorSetting::Gainceiling(_) => SensorControl::Gainceiling,
+            SensorSetting::Bpc(_) => SensorControl::Bpc,
+            SensorSetting::Wpc(_) => SensorControl::Wpc,
+            SensorSetting::RawGma(_) => SensorControl::RawGma,
+            SensorSetting::Lenc(_) => SensorControl::Lenc,
+            SensorSetting::Hmirror(_) => SensorControl::Hmirror,
+            SensorSetting::Vflip(_) => SensorControl::Vflip,
+            SensorSetting::Dcw(_) => SensorControl::Dcw,
+            SensorSetting::Colorbar(_) => SensorControl::Colorbar,
+        }
+    }
+
+    pub fn apply(&self, sensor: &CameraSensor) -> Result<(), SensorError> {
+        match *self {
+            SensorSetting::Pixformat(format) => sensor.set_pixformat(format),
+            SensorSetting::Framesize(size) => sensor.set_framesize(size),
+            SensorSetting::XclkMhz(mhz) => sensor.set_xclk_mhz(mhz),
+            SensorSetting::Quality(value) => sensor.set_quality(value),
+            SensorSetting::Brightness(value) => sensor.set_brightness(value),
+            SensorSetting::Contrast(value) => sensor.set_contrast(value),
+            SensorSetting::Saturation(value) => sensor.set_saturation(value),
+            SensorSetting::Sharpness(value) => sensor.set_sharpness(value),
+            SensorSetting::Denoise(value) => sensor.set_denoise(value),
+            SensorSetting::SpecialEffect(value) => sensor.set_special_effect(value),
+            SensorSetting::Whitebal(enable) => sensor.set_whitebal(enable),
+            SensorSetting::AwbGain(enable) => sensor.set_awb_gain(enable),
+            SensorSetting::WbMode(value) => sensor.set_wb_mode(value),
+            SensorSetting::ExposureCtrl(enable) => sensor.set_exposure_ctrl(enable),
+            SensorSetting::Aec2(enable) => sensor.set_aec2(enable),
+            SensorSetting::AeLevel(value) => sensor.set_ae_level(value),
+            SensorSetting::AecValue(value) => sensor.set_aec_value(value),
+            SensorSetting::GainCtrl(enable) => sensor.set_gain_ctrl(enable),
+            SensorSetting::AgcGain(value) => sensor.set_agc_gain(value),
+            SensorSetting::Gainceiling(value) => sensor.set_gainceiling(value),
+            SensorSetting::Bpc(enable) => sensor.set_bpc(enable),
+            SensorSetting::Wpc(enable) => sensor.set_wpc(enable),
+            SensorSetting::RawGma(enable) => sensor.set_raw_gma(enable),
+            SensorSetting::Lenc(enable) => sensor.set_lenc(enable),
+            SensorSetting::Hmirror(enable) => sensor.set_hmirror(enable),
+            SensorSetting::Vflip(enable) => sensor.set_vflip(enable),
+            SensorSetting::Dcw(enable) => sensor.set_dcw(enable),
+            SensorSetting::Colorbar(enable) => sensor.set_colorbar(enable),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingOutcome {
+    Applied,
+    Unsupported,
+    Failed(SensorError),
+}
+
+#[derive(Debug)]
+pub struct ProfileReport {
+    pub profile: String,
+    pub results: Vec<(SensorSetting, SettingOutcome)>,
+}
+
+impl ProfileReport {
+    pub fn applied(&self) -> impl Iterator<Item = &SensorSetting> {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, SettingOutcome::Applied))
+            .map(|(setting, _)| setting)
+    }
+
+    pub fn unsupported(&self) -> impl Iterator<Item = &SensorSetting> {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, SettingOutcome::Unsupported))
+            .map(|(setting, _)| setting)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&SensorSetting, &SensorError)> {
+        self.results
+            .iter()
+            .filter_map(|(setting, outcome)| match outcome {
+                SettingOutcome::Failed(err) => Some((setting, err)),
+                _ => None,
+            })
+    }
+
+    /// True when nothing failed, unsupported controls don't count
+    pub fn is_ok(&self) -> bool {
+        self.failed().next().is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SensorProfile {
+    pub name: String,
+    pub settings: Vec<SensorSetting>,
+}
+
+impl SensorProfile {
+    pub fn new(name: impl Into<String>, settings: Vec<SensorSetting>) -> Self {
+        Self {
+            name: name.into(),
+            settings,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Applies every setting in order, carrying on past the ones that fail
+    pub fn apply(&self, sensor: &CameraSensor) -> ProfileReport {
+        let results = self
+            .settings
+            .iter()
+            .map(|setting| {
+                let outcome = match setting.apply(sensor) {
+                    Ok(()) => SettingOutcome::Applied,
+                    Err(SensorError::Unsupported(_)) => SettingOutcome::Unsupported,
+                    Err(err) => SettingOutcome::Failed(err),
+                };
+                (*setting, outcome)
+            })
+            .collect();
+
+        ProfileReport {
+            profile: self.name.clone(),
+            results,
+        }
+    }
+
+    pub fn low_noise() -> Self {
+        Self::new(
+            "low-noise",
+            vec![
+                // A slower sensor clock lowers EMI and lets auto exposure favor longer
+                // integration over analog gain, which keeps JPEG size stable but cleaner.
+                SensorSetting::XclkMhz(10),
+                SensorSetting::Gainceiling(GainCeiling::X2),
+                SensorSetting::Aec2(true),
+                SensorSetting::Bpc(true),
+                SensorSetting::Wpc(true),
+                SensorSetting::RawGma(true),
+                SensorSetting::Lenc(true),
+            ],
+        )
+    }
+
+    pub fn daylight() -> Self {
+        Self::new(
+            "daylight",
+            vec![
+                SensorSetting::Whitebal(true),
+                SensorSetting::AwbGain(true),
+                SensorSetting::WbMode(1),
+                SensorSetting::ExposureCtrl(true),
+                SensorSetting::Aec2(false),
+                SensorSetting::AeLevel(0),
+                SensorSetting::GainCtrl(true),
+                SensorSetting::Gainceiling(GainCeiling::X2),
+                SensorSetting::Saturation(1),
+                SensorSetting::Lenc(true),
+            ],
+        )
+    }
+
+    pub fn night() -> Self {
+        Self::new(
+            "night",
+            vec![
+                // let exposure run long and allow high gain, then clean up the hot pixels
+                SensorSetting::ExposureCtrl(true),
+                SensorSetting::Aec2(true),
+                SensorSetting::AeLevel(2),
+                SensorSetting::GainCtrl(true),
+                SensorSetting::Gainceiling(GainCeiling::X128),
+                SensorSetting::Brightness(1),
+                SensorSetting::Bpc(true),
+                SensorSetting::Wpc(true),
+                SensorSetting::Denoise(4),
+            ],
+        )
+    }
+
+    pub fn fast_motion() -> Self {
+        Self::new(
+            "fast-motion",
+            vec![
+                // a short fixed exposure avoids motion blur, gain makes up for the light
+                SensorSetting::ExposureCtrl(false),
+                SensorSetting::Aec2(false),
+                SensorSetting::AecValue(100),
+                SensorSetting::GainCtrl(true),
+                SensorSetting::Gainceiling(GainCeiling::X32),
+                SensorSetting::Dcw(true),
+            ],
+        )
+    }
+
+    pub fn document() -> Self {
+        Self::new(
+            "document",
+            vec![
+                SensorSetting::Whitebal(true),
+                SensorSetting::WbMode(0),
+                SensorSetting::Contrast(2),
+                SensorSetting::Sharpness(2),
+                SensorSetting::Saturation(-2),
+                SensorSetting::Quality(8),
+                SensorSetting::Lenc(true),
+            ],
+        )
+    }
+
+    pub fn builtin() -> Vec<Self> {
+        vec![
+            Self::low_noise(),
+            Self::daylight(),
+            Self::night(),
+            Self::fast_motion(),
+            Self::document(),
+        ]
+    }
+}
+
+/// Profiles looked up by name, starting with the built-in ones
+#[derive(Debug, Clone)]
+pub struct ProfileRegistry {
+    profiles: Vec<SensorProfile>,
+}
+
+impl Default for ProfileRegistry {
+    fn default() -> Self {
+        Self {
+            profiles: SensorProfile::builtin(),
+        }
+    }
+}
+
+impl ProfileRegistry {
+    pub fn empty() -> Self {
+        Self {
+            profiles: Vec::new(),
+        }
+    }
+
+    /// Adds a profile, replacing any other with the same name
+    pub fn register(&mut self, profile: SensorProfile) {
+        self.profiles.retain(|p| p.name != profile.name);
+        self.profiles.push(profile);
+    }
+
+    /// Registers every profile of a JSON array
+    pub fn load_json(&mut self, json: &str) -> Result<(), serde_json::Error> {
+        let profiles: Vec<SensorProfile> = serde_json::from_str(json)?;
+        for profile in profiles {
+            self.register(profile);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SensorProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().map(|p| p.name.as_str())
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+
+use super::{FrameSize, SensorModel};
+
+// window and offsets stay on this grid, the OV2640 HSIZE8/VSIZE8 registers
+// count the window in 8 pixel units
+const ALIGN: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum RoiError {
+    #[error("{0:?} doesn't take a raw window")]
+    UnsupportedSensor(SensorModel),
+    #[error("{roi:?} doesn't fit in the {width}x{height} pixel array")]
+    OutOfBounds { roi: Roi, width: u32, height: u32 },
+    #[error("{roi:?} is smaller than the {output} output")]
+    SmallerThanOutput { roi: Roi, output: FrameSize },
+}
+
+/// Arguments of [`CameraSensor::set_res_raw`](super::CameraSensor::set_res_raw)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawWindow {
+    pub start_x: i32,
+    pub start_y: i32,
+    pub end_x: i32,
+    pub end_y: i32,
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub total_x: i32,
+    pub total_y: i32,
+    pub output_x: i32,
+    pub output_y: i32,
+    pub scale: bool,
+    pub binning: bool,
+}
+
+/// A window on the sensor's pixel array, in native pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole pixel array
+    pub fn full(model: SensorModel) -> Result<Self, RoiError> {
+        let (width, height) = native_resolution(model)?;
+        Ok(Self::new(0, 0, width, height))
+    }
+
+    /// Same center, `factor` times smaller, kept inside the pixel array
+    pub fn zoom(&self, factor: f32, model: SensorModel) -> Result<Self, RoiError> {
+        let (native_width, native_height) = native_resolution(model)?;
+        let factor = factor.max(f32::EPSILON);
+
+        let width = ((self.width as f32 / factor) as u32).clamp(ALIGN, native_width);
+        let height = ((self.height as f32 / factor) as u32).clamp(ALIGN, native_height);
+        let center_x = self.x + self.width / 2;
+        let center_y = self.y + self.height / 2;
+
+        Ok(Self::new(
+            center_x.saturating_sub(width / 2),
+            center_y.saturating_sub(height / 2),
+            width,
+            height,
+        )
+        .clamped(native_width, native_height))
+    }
+
+    /// Moves the window by native pixels, stopping at the edges
+    pub fn pan(&self, dx: i32, dy: i32, model: SensorModel) -> Result<Self, RoiError> {
+        let (native_width, native_height) = native_resolution(model)?;
+
+        Ok(Self::new(
+            self.x.saturating_add_signed(dx),
+            self.y.saturating_add_signed(dy),
+            self.width,
+            self.height,
+        )
+        .clamped(native_width, native_height))
+    }
+
+    /// Computes the raw window for `model`, checking it against the native
+    /// resolution. The output is scaled down from the window, never up.
+    pub fn raw_window(&self, model: SensorModel, output: FrameSize) -> Result<RawWindow, RoiError> {
+        let (native_width, native_height) = native_resolution(model)?;
+
+        let roi = self.aligned();
+        if roi.width == 0
+            || roi.height == 0
+            || roi.x.saturating_add(roi.width) > native_width
+            || roi.y.saturating_add(roi.height) > native_height
+        {
+            return Err(RoiError::OutOfBounds {
+                roi: *self,
+                width: native_width,
+                height: native_height,
+            });
+        }
+        if output.width() as u32 > roi.width || output.height() as u32 > roi.height {
+            return Err(RoiError::SmallerThanOutput { roi: *self, output });
+        }
+
+        let scale = output.width() as u32 != roi.width || output.height() as u32 != roi.height;
+
+        let window = match model {
+            // startX picks the sensor mode, the window is given with offset and size
+            SensorModel::Ov2640 => RawWindow {
+                start_x: 0,
+                start_y: 0,
+                end_x: 0,
+                end_y: 0,
+                offset_x: roi.x as i32,
+                offset_y: roi.y as i32,
+                total_x: roi.width as i32,
+                total_y: roi.height as i32,
+                output_x: output.width() as i32,
+                output_y: output.height() as i32,
+                scale,
+                binning: false,
+            },
+            // array window with the ISP margin around it, line and frame totals of the full frame
+            SensorModel::Ov3660 | SensorModel::Ov5640 => {
+                let (margin_x, margin_y, total_x, total_y) = match model {
+                    SensorModel::Ov3660 => (16, 6, 2300, 1564),
+                    _ => (32, 16, 2844, 1968),
+                };
+                RawWindow {
+                    start_x: roi.x as i32,
+                    start_y: roi.y as i32,
+                    end_x: (roi.x + roi.width + 2 * margin_x) as i32 - 1,
+                    end_y: (roi.y + roi.height + 2 * margin_y) as i32 - 1,
+                    offset_x: margin_x as i32,
+                    offset_y: margin_y as i32,
+                    total_x,
+                    total_y,
+                    output_x: output.width() as i32,
+                    output_y: output.height() as i32,
+                    scale,
+                    binning: false,
+                }
+            }
+            model => return Err(RoiError::UnsupportedSensor(model)),
+        };
+
+        Ok(window)
+    }
+
+    fn aligned(&self) -> Self {
+        Self::new(
+            self.x / ALIGN * ALIGN,
+            self.y / ALIGN * ALIGN,
+            self.width / ALIGN * ALIGN,
+            self.height / ALIGN * ALIGN,
+        )
+    }
+
+    fn clamped(&self, native_width: u32, native_height: u32) -> Self {
+        let width = self.width.min(native_width);
+        let height = self.height.min(native_height);
+        Self::new(
+            self.x.min(native_width - width),
+            self.y.min(native_height - height),
+            width,
+            height,
+        )
+    }
+}
+
+/// Pixel array the raw window is given in, for the sensors that take one
+pub fn native_resolution(model: SensorModel) -> Result<(u32, u32), RoiError> {
+    match model {
+        SensorModel::Ov2640 => Ok((1600, 1200)),
+        SensorModel::Ov3660 => Ok((2048, 1536)),
+        SensorModel::Ov5640 => Ok((2560, 1920)),
+        model => Err(RoiError::UnsupportedSensor(model)),
+    }
+}
+
+/// Zoom and pan in steps, keeping the output size.
+///
+/// ```ignore
+/// let mut zoom = DigitalZoom::new(sensor.model()?, FrameSize::Vga)?;
+/// zoom.zoom_in();
+/// zoom.pan(200, 0);
+/// sensor.set_roi(zoom.roi(), zoom.output())?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DigitalZoom {
+    model: SensorModel,
+    output: FrameSize,
+    roi: Roi,
+    step: f32,
+}
+
+impl DigitalZoom {
+    pub fn new(model: SensorModel, output: FrameSize) -> Result<Self, RoiError> {
+        Ok(Self {
+            model,
+            output,
+            roi: Roi::full(model)?,
+            step: 1.25,
+        })
+    }
+
+    /// Zoom factor of one step
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = step.max(1.0);
+        self
+    }
+
+    pub fn roi(&self) -> &Roi {
+        &self.roi
+    }
+
+    pub fn output(&self) -> FrameSize {
+        self.output
+    }
+
+    /// How much of the field is cut away, 1.0 is the whole array
+    pub fn factor(&self) -> f32 {
+        native_resolution(self.model).map_or(1.0, |(width, _)| width as f32 / self.roi.width as f32)
+    }
+
+    /// Stops once the window would get smaller than the output
+    pub fn zoom_in(&mut self) {
+        if let Ok(roi) = self.roi.zoom(self.step, self.model) {
+            if roi.width >= self.output.width() as u32 && roi.height >= self.output.height() as u32
+            {
+                self.roi = roi;
+            }
+        }
+    }
+
+    pub fn zoom_out(&mut self) {
+        if let Ok(roi) = self.roi.zoom(1.0 / self.step, self.model) {
+            self.roi = roi;
+        }
+    }
+
+    pub fn pan(&mut self, dx: i32, dy: i32) {
+        if let Ok(roi) = self.roi.pan(dx, dy, self.model) {
+            self.roi = roi;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        if let Ok(roi) = Roi::full(self.model) {
+            self.roi = roi;
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use std::marker::PhantomData;
+
+use esp_idf_sys::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    FrameSize, PixelFormat, Roi, RoiError, SensorCapabilities, SensorControl, SensorModel,
+    UnknownFrameSize, UnknownPixelFormat,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SensorError {
+    #[error("no camera sensor detected")]
+    NoSensor,
+    #[error("{0:?} is not supported by this sensor")]
+    Unsupported(SensorControl),
+    #[error("sensor error: {0}")]
+    Esp(#[from] EspError),
+    #[error(transparent)]
+    UnknownPixelFormat(#[from] UnknownPixelFormat),
+    #[error(transparent)]
+    UnknownFrameSize(#[from] UnknownFrameSize),
+    #[error(transparent)]
+    UnknownGainCeiling(#[from] UnknownGainCeiling),
+    #[error(transparent)]
+    Roi(#[from] RoiError),
+}
+
+/// Highest analog gain the automatic gain control may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum GainCeiling {
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("unknown gain ceiling {0}")]
+pub struct UnknownGainCeiling(pub camera::gainceiling_t);
+
+impl TryFrom<camera::gainceiling_t> for GainCeiling {
+    type Error = UnknownGainCeiling;
+
+    fn try_from(value: camera::gainceiling_t) -> Result<Self, Self::Error> {
+        Ok(match value {
+            camera::gainceiling_t_GAINCEILING_2X => GainCeiling::X2,
+            camera::gainceiling_t_GAINCEILING_4X => GainCeiling::X4,
+            camera::gainceiling_t_GAINCEILING_8X => GainCeiling::X8,
+            camera::gainceiling_t_GAINCEILING_16X => GainCeiling::X16,
+            camera::gainceiling_t_GAINCEILING_32X => GainCeiling::X32,
+            camera::gainceiling_t_GAINCEILING_64X => GainCeiling::X64,
+            camera::gainceiling_t_GAINCEILING_128X => GainCeiling::X128,
+            _ => return Err(UnknownGainCeiling(value)),
+        })
+    }
+}
+
+impl From<GainCeiling> for camera::gainceiling_t {
+    fn from(value: GainCeiling) -> Self {
+        match value {
+            GainCeiling::X2 => camera::gainceiling_t_GAINCEILING_2X,
+            GainCeiling::X4 => camera::gainceiling_t_GAINCEILING_4X,
+            GainCeiling::X8 => camera::gainceiling_t_GAINCEILING_8X,
+            GainCeiling::X16 => camera::gainceiling_t_GAINCEILING_16X,
+            GainCeiling::X32 => camera::gainceiling_t_GAINCEILING_32X,
+            GainCeiling::X64 => camera::gainceiling_t_GAINCEILING_64X,
+            GainCeiling::X128 => camera::gainceiling_t_GAINCEILING_128X,
+        }
+    }
+}
+
+/// Every setting tracked in `sensor_t.status`, enough to restore a sensor to
+/// the exact same configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SensorSettings {
+    pub pixel_format: PixelFormat,
+    pub frame_size: FrameSize,
+    pub quality: i32,
+    pub brightness: i32,
+    pub contrast: i32,
+    pub saturation: i32,
+    pub sharpness: i32,
+    pub denoise: i32,
+    pub special_effect: i32,
+    pub wb_mode: i32,
+    pub whitebal: bool,
+    pub awb_gain: bool,
+    pub exposure_ctrl: bool,
+    pub aec2: bool,
+    pub ae_level: i32,
+    pub aec_value: i32,
+    pub gain_ctrl: bool,
+    pub agc_gain: i32,
+    pub gainceiling: GainCeiling,
+    pub bpc: bool,
+    pub wpc: bool,
+    pub raw_gma: bool,
+    pub lenc: bool,
+    pub hmirror: bool,
+    pub vflip: bool,
+    pub dcw: bool,
+    pub colorbar: bool,
+}
+
+macro_rules! sensor_call {
+    ($self:ident, $control:ident, $func:ident $(, $arg:expr)*) => {{
+        let sensor = $self.raw()?;
+        match unsafe { (*sensor).$func } {
+            Some(func) => Ok(esp!(unsafe { func(sensor $(, $arg)*) })?),
+            None => Err(SensorError::Unsupported(SensorControl::$control)),
+        }
+    }};
+}
+
+pub struct CameraSensor<'a> {
+    sensor: *mut camera::sensor_t,
+    ledc_timer: ledc_timer_t,
+    _p: PhantomData<&'a camera::sensor_t>,
+}
+
+impl<'a> CameraSensor<'a> {
+    pub(super) fn new(sensor: *mut camera::sensor_t, ledc_timer: ledc_timer_t) -> Self {
+        Self {
+            sensor,
+            ledc_timer,
+            _p: PhantomData,
+        }
+    }
+
+    fn raw(&self) -> Result<*mut camera::sensor_t, SensorError> {
+        if self.sensor.is_null() {
+            Err(SensorError::NoSensor)
+        } else {
+            Ok(self.sensor)
+        }
+    }
+
+    fn status(&self) -> Result<camera::camera_status_t, SensorError> {
+        let sensor = self.raw()?;
+        Ok(unsafe { (*sensor).status })
+    }
+
+    pub fn model(&self) -> Result<SensorModel, SensorError> {
+        let sensor = self.raw()?;
+        Ok(SensorModel::from_pid(unsafe { (*sensor).id.PID }))
+    }
+
+    pub fn capabilities(&self) -> Result<SensorCapabilities, SensorError> {
+        let sensor = self.raw()?;
+        let model = self.model()?;
+
+        let info = unsafe { camera::esp_camera_sensor_get_info(&mut (*sensor).id) };
+        let (max_frame_size, jpeg) = if info.is_null() {
+            (None, false)
+        } else {
+            unsafe {
+                (
+                    FrameSize::try_from((*info).max_size).ok(),
+                    (*info).support_jpeg,
+                )
+            }
+        };
+
+        let s = unsafe { &*sensor };
+        let controls = [
+            (SensorControl::InitStatus, s.init_status.is_some()),
+            (SensorControl::Reset, s.reset.is_some()),
+            (SensorControl::Pixformat, s.set_pixformat.is_some()),
+            (SensorControl::Framesize, s.set_framesize.is_some()),
+            (SensorControl::Contrast, s.set_contrast.is_some()),
+            (SensorControl::Brightness, s.set_brightness.is_some()),
+            (SensorControl::Saturation, s.set_saturation.is_some()),
+            (SensorControl::Sharpness, s.set_sharpness.is_some()),
+            (SensorControl::Denoise, s.set_denoise.is_some()),
+            (SensorControl::Gainceiling, s.set_gainceiling.is_some()),
+            (SensorControl::Quality, s.set_quality.is_some() && jpeg),
+            (SensorControl::Colorbar, s.set_colorbar.is_some()),
+            (SensorControl::Whitebal, s.set_whitebal.is_some()),
+            (SensorControl::GainCtrl, s.set_gain_ctrl.is_some()),
+            (SensorControl::ExposureCtrl, s.set_exposure_ctrl.is_some()),
+            (SensorControl::Hmirror, s.set_hmirror.is_some()),
+            (SensorControl::Vflip, s.set_vflip.is_some()),
+            (SensorControl::Aec2, s.set_aec2.is_some()),
+            (SensorControl::AwbGain, s.set_awb_gain.is_some()),
+            (SensorControl::AgcGain, s.set_agc_gain.is_some()),
+            (SensorControl::AecValue, s.set_aec_value.is_some()),
+            (SensorControl::SpecialEffect, s.set_special_effect.is_some()),
+            (SensorControl::WbMode, s.set_wb_mode.is_some()),
+            (SensorControl::AeLevel, s.set_ae_level.is_some()),
+            (SensorControl::Dcw, s.set_dcw.is_some()),
+            (SensorControl::Bpc, s.set_bpc.is_some()),
+            (SensorControl::Wpc, s.set_wpc.is_some()),
+            (SensorControl::RawGma, s.set_raw_gma.is_some()),
+            (SensorControl::Lenc, s.set_lenc.is_some()),
+            (SensorControl::GetReg, s.get_reg.is_some()),
+            (SensorControl::SetReg, s.set_reg.is_some()),
+            (SensorControl::ResRaw, s.set_res_raw.is_some()),
+            (SensorControl::Pll, s.set_pll.is_some()),
+            (SensorControl::Xclk, s.set_xclk.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(control, supported)| supported.then_some(control))
+        .collect();
+
+        Ok(SensorCapabilities {
+            model,
+            max_frame_size,
+            jpeg,
+            controls,
+        })
+    }
+
+    pub fn init_status(&self) -> Result<(), SensorError> {
+        sensor_call!(self, InitStatus, init_status)
+    }
+    pub fn reset(&self) -> Result<(), SensorError> {
+        sensor_call!(self, Reset, reset)
+    }
+    pub fn set_pixformat(&self, format: PixelFormat) -> Result<(), SensorError> {
+        sensor_call!(self, Pixformat, set_pixformat, format.into())
+    }
+    pub fn set_framesize(&self, framesize: FrameSize) -> Result<(), SensorError> {
+        sensor_call!(self, Framesize, set_framesize, framesize.into())
+    }
+    pub fn set_contrast(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Contrast, set_contrast, level)
+    }
+    pub fn set_brightness(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Brightness, set_brightness, level)
+    }
+    pub fn set_saturation(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Saturation, set_saturation, level)
+    }
+    pub fn set_sharpness(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Sharpness, set_sharpness, level)
+    }
+    pub fn set_denoise(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Denoise, set_denoise, level)
+    }
+    pub fn set_gainceiling(&self, gainceiling: GainCeiling) -> Result<(), SensorError> {
+        sensor_call!(self, Gainceiling, set_gainceiling, gainceiling.into())
+    }
+    pub fn set_quality(&self, quality: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Quality, set_quality, quality)
+    }
+    pub fn set_colorbar(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Colorbar, set_colorbar, enable as i32)
+    }
+    pub fn set_whitebal(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Whitebal, set_whitebal, enable as i32)
+    }
+    pub fn set_gain_ctrl(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, GainCtrl, set_gain_ctrl, enable as i32)
+    }
+    pub fn set_exposure_ctrl(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, ExposureCtrl, set_exposure_ctrl, enable as i32)
+    }
+    pub fn set_hmirror(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Hmirror, set_hmirror, enable as i32)
+    }
+    pub fn set_vflip(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Vflip, set_vflip, enable as i32)
+    }
+    pub fn set_aec2(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Aec2, set_aec2, enable as i32)
+    }
+    pub fn set_awb_gain(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, AwbGain, set_awb_gain, enable as i32)
+    }
+    pub fn set_agc_gain(&self, gain: i32) -> Result<(), SensorError> {
+        sensor_call!(self, AgcGain, set_agc_gain, gain)
+    }
+    pub fn set_aec_value(&self, gain: i32) -> Result<(), SensorError> {
+        sensor_call!(self, AecValue, set_aec_value, gain)
+    }
+    pub fn set_special_effect(&self, effect: i32) -> Result<(), SensorError> {
+        sensor_call!(self, SpecialEffect, set_special_effect, effect)
+    }
+    pub fn set_wb_mode(&self, mode: i32) -> Result<(), SensorError> {
+        sensor_call!(self, WbMode, set_wb_mode, mode)
+    }
+    pub fn set_ae_level(&self, level: i32) -> Result<(), SensorError> {
+        sensor_call!(self, AeLevel, set_ae_level, level)
+    }
+    pub fn set_dcw(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Dcw, set_dcw, enable as i32)
+    }
+    pub fn set_bpc(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Bpc, set_bpc, enable as i32)
+    }
+    pub fn set_wpc(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Wpc, set_wpc, enable as i32)
+    }
+    pub fn set_raw_gma(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, RawGma, set_raw_gma, enable as i32)
+    }
+    pub fn set_lenc(&self, enable: bool) -> Result<(), SensorError> {
+        sensor_call!(self, Lenc, set_lenc, enable as i32)
+    }
+    pub fn get_reg(&self, reg: i32, mask: i32) -> Result<i32, SensorError> {
+        let sensor = self.raw()?;
+        let get_reg =
+            unsafe { (*sensor).get_reg }.ok_or(SensorError::Unsupported(SensorControl::GetReg))?;
+
+        // the driver returns the masked register value, or -1 on failure
+        let value = unsafe { get_reg(sensor, reg, mask) };
+        if value < 0 {
+            Err(EspError::from_infallible::<ESP_FAIL>().into())
+        } else {
+            Ok(value)
+        }
+    }
+    pub fn set_reg(&self, reg: i32, mask: i32, value: i32) -> Result<(), SensorError> {
+        sensor_call!(self, SetReg, set_reg, reg, mask, value)
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_res_raw(
+        &self,
+        start_x: i32,
+        start_y: i32,
+        end_x: i32,
+        end_y: i32,
+        offset_x: i32,
+        offset_y: i32,
+        total_x: i32,
+        total_y: i32,
+        output_x: i32,
+        output_y: i32,
+        scale: bool,
+        binning: bool,
+    ) -> Result<(), SensorError> {
+        sensor_call!(
+            self,
+            ResRaw,
+            set_res_raw,
+            start_x,
+            start_y,
+            end_x,
+            end_y,
+            offset_x,
+            offset_y,
+            total_x,
+            total_y,
+            output_x,
+            output_y,
+            scale,
+            binning
+        )
+    }
+    /// Crops to `roi` and scales it down to `output`, checked for the detected sensor
+    pub fn set_roi(&self, roi: &Roi, output: FrameSize) -> Result<(), SensorError> {
+        let window = roi.raw_window(self.model()?, output)?;
+        self.set_res_raw(
+            window.start_x,
+            window.start_y,
+            window.end_x,
+            window.end_y,
+            window.offset_x,
+            window.offset_y,
+            window.total_x,
+            window.total_y,
+            window.output_x,
+            window.output_y,
+            window.scale,
+            window.binning,
+        )
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_pll(
+        &self,
+        bypass: i32,
+        mul: i32,
+        sys: i32,
+        root: i32,
+        pre: i32,
+        seld5: i32,
+        pclken: i32,
+        pclk: i32,
+    ) -> Result<(), SensorError> {
+        sensor_call!(self, Pll, set_pll, bypass, mul, sys, root, pre, seld5, pclken, pclk)
+    }
+    pub fn set_xclk(&self, timer: i32, xclk: i32) -> Result<(), SensorError> {
+        sensor_call!(self, Xclk, set_xclk, timer, xclk)
+    }
+    /// Changes XCLK on the LEDC timer the camera was configured with
+    pub fn set_xclk_mhz(&self, xclk: i32) -> Result<(), SensorError> {
+        self.set_xclk(self.ledc_timer as i32, xclk)
+    }
+
+    pub fn pixformat(&self) -> Result<PixelFormat, SensorError> {
+        let sensor = self.raw()?;
+        Ok(PixelFormat::try_from(unsafe { (*sensor).pixformat })?)
+    }
+    pub fn framesize(&self) -> Result<FrameSize, SensorError> {
+        Ok(FrameSize::try_from(self.status()?.framesize)?)
+    }
+    pub fn quality(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.quality as i32)
+    }
+    pub fn brightness(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.brightness as i32)
+    }
+    pub fn contrast(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.contrast as i32)
+    }
+    pub fn saturation(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.saturation as i32)
+    }
+    pub fn sharpness(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.sharpness as i32)
+    }
+    pub fn denoise(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.denoise as i32)
+    }
+    pub fn special_effect(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.special_effect as i32)
+    }
+    pub fn wb_mode(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.wb_mode as i32)
+    }
+    pub fn whitebal(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.awb != 0)
+    }
+    pub fn awb_gain(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.awb_gain != 0)
+    }
+    pub fn exposure_ctrl(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.aec != 0)
+    }
+    pub fn aec2(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.aec2 != 0)
+    }
+    pub fn ae_level(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.ae_level as i32)
+    }
+    pub fn aec_value(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.aec_value as i32)
+    }
+    pub fn gain_ctrl(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.agc != 0)
+    }
+    pub fn agc_gain(&self) -> Result<i32, SensorError> {
+        Ok(self.status()?.agc_gain as i32)
+    }
+    pub fn gainceiling(&self) -> Result<GainCeiling, SensorError> {
+        Ok(GainCeiling::try_from(
+            self.status()?.gainceiling as camera::gainceiling_t,
+        )?)
+    }
+    pub fn bpc(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.bpc != 0)
+    }
+    pub fn wpc(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.wpc != 0)
+    }
+    pub fn raw_gma(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.raw_gma != 0)
+    }
+    pub fn lenc(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.lenc != 0)
+    }
+    pub fn hmirror(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.hmirror != 0)
+    }
+    pub fn vflip(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.vflip != 0)
+    }
+    pub fn dcw(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.dcw != 0)
+    }
+    pub fn colorbar(&self) -> Result<bool, SensorError> {
+        Ok(self.status()?.colorbar != 0)
+    }
+
+    pub fn settings(&self) -> Result<SensorSettings, SensorError> {
+        let status = self.status()?;
+
+        Ok(SensorSettings {
+            pixel_format: self.pixformat()?,
+            frame_size: FrameSize::try_from(status.framesize)?,
+            quality: status.quality as i32,
+            brightness: status.brightness as i32,
+            contrast: status.contrast as i32,
+            saturation: status.saturation as i32,
+            sharpness: status.sharpness as i32,
+            denoise: status.denoise as i32,
+            special_effect: status.special_effect as i32,
+            wb_mode: status.wb_mode as i32,
+            whitebal: status.awb != 0,
+            awb_gain: status.awb_gain != 0,
+            exposure_ctrl: status.aec != 0,
+            aec2: status.aec2 != 0,
+            ae_level: status.ae_level as i32,
+            aec_value: status.aec_value as i32,
+            gain_ctrl: status.agc != 0,
+            agc_gain: status.agc_gain as i32,
+            gainceiling: GainCeiling::try_from(status.gainceiling as camera::gainceiling_t)?,
+            bpc: status.bpc != 0,
+            wpc: status.wpc != 0,
+            raw_gma: status.raw_gma != 0,
+            lenc: status.lenc != 0,
+            hmirror: status.hmirror != 0,
+            vflip: status.vflip != 0,
+            dcw: status.dcw != 0,
+            colorbar: status.colorbar != 0,
+        })
+    }
+
+    /// Restores a snapshot taken with [`settings`](Self::settings), skipping
+    /// the controls this sensor doesn't implement
+    pub fn apply_settings(&self, settings: &SensorSettings) -> Result<(), SensorError> {
+        let results = [
+            self.set_pixformat(settings.pixel_format),
+            self.set_framesize(settings.frame_size),
+            self.set_quality(settings.quality),
+            self.set_brightness(settings.brightness),
+            self.set_contrast(settings.contrast),
+            self.set_saturation(settings.saturation),
+            self.set_sharpness(settings.sharpness),
+            self.set_denoise(settings.denoise),
+            self.set_special_effect(settings.special_effect),
+            self.set_whitebal(settings.whitebal),
+            self.set_awb_gain(settings.awb_gain),
+            self.set_wb_mode(settings.wb_mode),
+            // automatic controls go first, the manual values only stick when they're off
+            self.set_exposure_ctrl(settings.exposure_ctrl),
+            self.set_aec2(settings.aec2),
+            self.set_ae_level(settings.ae_level),
+            self.set_aec_value(settings.aec_value),
+            self.set_gain_ctrl(settings.gain_ctrl),
+            self.set_agc_gain(settings.agc_gain),
+            self.set_gainceiling(settings.gainceiling),
+            self.set_bpc(settings.bpc),
+            self.set_wpc(settings.wpc),
+            self.set_raw_gma(settings.raw_gma),
+            self.set_lenc(settings.lenc),
+            self.set_hmirror(settings.hmirror),
+            self.set_vflip(settings.vflip),
+            self.set_dcw(settings.dcw),
+            self.set_colorbar(settings.colorbar),
+        ];
+
+        for result in results {
+            match result {
+                Ok(()) | Err(SensorError::Unsupported(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::{uptime, Camera, OwnedFrame, ProfileReport, SensorProfile};
+
+// copying frames out of the driver doesn't need much
+const CAPTURE_TASK_STACK_SIZE: usize = 8 * 1024;
+// keeps the task from spinning while the camera is in standby
+const NO_FRAME_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    #[error("the capture task is not running")]
+    Stopped,
+    #[error("no frame could be captured")]
+    CaptureFailed,
+    #[error("could not start the capture task: {0}")]
+    Spawn(#[from] std::io::Error),
+}
+
+/// Latency seen by one consumer, from request to reply for snapshots and from
+/// capture to receive for subscriptions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub frames: u32,
+    /// Broadcast frames skipped because the consumer's queue was full
+    pub dropped: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl LatencyStats {
+    pub fn mean(&self) -> Duration {
+        if self.frames == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.frames
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        if self.frames == 0 || latency < self.min {
+            self.min = latency;
+        }
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.frames += 1;
+    }
+}
+
+type Stats = Arc<Mutex<HashMap<String, LatencyStats>>>;
+type Job = Box<dyn FnOnce(&mut Camera<'static>) + Send>;
+
+enum Command {
+    Snapshot(SyncSender<Option<Arc<OwnedFrame>>>),
+    Subscribe(String, SyncSender<Arc<OwnedFrame>>),
+    Configure(Job),
+    Stop,
+}
+
+/// Owns the camera on a dedicated capture task, so several consumers can
+/// share it without calling into the driver concurrently.
+///
+/// ```ignore
+/// let service = Arc::new(CameraService::start(camera)?);
+/// let frame = service.snapshot("http")?;
+/// let frames = service.subscribe("stream", 2)?;
+/// ```
+pub struct CameraService {
+    commands: Sender<Command>,
+    stats: Stats,
+    task: Option<JoinHandle<Camera<'static>>>,
+}
+
+impl CameraService {
+    pub fn start(camera: Camera<'static>) -> Result<Self, ServiceError> {
+        let (commands, receiver) = mpsc::channel();
+        let stats = Stats::default();
+
+        let task_stats = stats.clone();
+        let task = std::thread::Builder::new()
+            .name("camera".into())
+            .stack_size(CAPTURE_TASK_STACK_SIZE)
+            .spawn(move || capture_task(camera, receiver, task_stats))?;
+
+        Ok(Self {
+            commands,
+            stats,
+            task: Some(task),
+        })
+    }
+
+    /// Captures a frame that started exposing after the request
+    pub fn snapshot(&self, consumer: &str) -> Result<Arc<OwnedFrame>, ServiceError> {
+        let requested = Instant::now();
+        let (reply, frame) = mpsc::sync_channel(1);
+        self.send(Command::Snapshot(reply))?;
+
+        let frame = frame
+            .recv()
+            .map_err(|_| ServiceError::Stopped)?
+            .ok_or(ServiceError::CaptureFailed)?;
+
+        record_latency(&self.stats, consumer, requested.elapsed());
+        Ok(frame)
+    }
+
+    /// Streams every captured frame, keeping at most `depth` queued for this
+    /// consumer. Frames that don't fit are dropped and counted in the stats.
+    pub fn subscribe(&self, consumer: &str, depth: usize) -> Result<Subscription, ServiceError> {
+        let (sender, frames) = mpsc::sync_channel(depth.max(1));
+        self.send(Command::Subscribe(consumer.to_string(), sender))?;
+
+        Ok(Subscription {
+            consumer: consumer.to_string(),
+            frames,
+            stats: self.stats.clone(),
+        })
+    }
+
+    /// Runs `f` on the capture task between two frames, so setting changes
+    /// are applied one at a time and never race a capture.
+    ///
+    /// ```ignore
+    /// service.configure(move |camera| camera.reconfigure(config))??;
+    /// ```
+    pub fn configure<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Camera<'static>) -> R + Send + 'static,
+    ) -> Result<R, ServiceError> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.send(Command::Configure(Box::new(move |camera| {
+            reply.send(f(camera)).ok();
+        })))?;
+
+        result.recv().map_err(|_| ServiceError::Stopped)
+    }
+
+    pub fn apply_profile(&self, profile: SensorProfile) -> Result<ProfileReport, ServiceError> {
+        self.configure(move |camera| camera.apply_profile(&profile))
+    }
+
+    pub fn stats(&self) -> HashMap<String, LatencyStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Stops the capture task and hands the camera back
+    pub fn stop(mut self) -> Option<Camera<'static>> {
+        self.shutdown()
+    }
+
+    fn send(&self, command: Command) -> Result<(), ServiceError> {
+        self.commands
+            .send(command)
+            .map_err(|_| ServiceError::Stopped)
+    }
+
+    fn shutdown(&mut self) -> Option<Camera<'static>> {
+        self.commands.send(Command::Stop).ok();
+        self.task.take()?.join().ok()
+    }
+}
+
+impl Drop for CameraService {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Frames broadcast by a [`CameraService`]
+pub struct Subscription {
+    consumer: String,
+    frames: Receiver<Arc<OwnedFrame>>,
+    stats: Stats,
+}
+
+impl Subscription {
+    /// Waits for the next frame, `None` once the service has stopped
+    pub fn recv(&self) -> Option<Arc<OwnedFrame>> {
+        let frame = self.frames.recv().ok()?;
+        self.record(&frame);
+        Some(frame)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Arc<OwnedFrame>> {
+        let frame = self.frames.recv_timeout(timeout).ok()?;
+        self.record(&frame);
+        Some(frame)
+    }
+
+    pub fn try_recv(&self) -> Option<Arc<OwnedFrame>> {
+        let frame = self.frames.try_recv().ok()?;
+        self.record(&frame);
+        Some(frame)
+    }
+
+    fn record(&self, frame: &OwnedFrame) {
+        record_latency(
+            &self.stats,
+            &self.consumer,
+            uptime().saturating_sub(frame.timestamp()),
+        );
+    }
+}
+
+fn record_latency(stats: &Stats, consumer: &str, latency: Duration) {
+    stats
+        .lock()
+        .unwrap()
+        .entry(consumer.to_string())
+        .or_default()
+        .record(latency);
+}
+
+fn capture_task(
+    mut camera: Camera<'static>,
+    commands: Receiver<Command>,
+    stats: Stats,
+) -> Camera<'static> {
+    let mut subscribers: Vec<(String, SyncSender<Arc<OwnedFrame>>)> = Vec::new();
+
+    loop {
+        // sleep on the queue while nobody is streaming
+        let command = if subscribers.is_empty() {
+            commands.recv().map_err(|_| TryRecvError::Disconnected)
+        } else {
+            commands.try_recv()
+        };
+
+        match command {
+            Ok(Command::Snapshot(reply)) => {
+                let frame = camera
+                    .capture_fresh()
+                    .and_then(|framebuffer| framebuffer.to_owned().ok())
+                    .map(Arc::new);
+                reply.send(frame).ok();
+            }
+            Ok(Command::Subscribe(consumer, sender)) => subscribers.push((consumer, sender)),
+            Ok(Command::Configure(job)) => job(&mut camera),
+            Ok(Command::Stop) | Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+
+        if subscribers.is_empty() {
+            continue;
+        }
+
+        let Some(frame) = camera
+            .get_framebuffer()
+            .and_then(|framebuffer| framebuffer.to_owned().ok())
+        else {
+            std::thread::sleep(NO_FRAME_BACKOFF);
+            continue;
+        };
+        let frame = Arc::new(frame);
+
+        subscribers.retain(|(consumer, sender)| match sender.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                stats
+                    .lock()
+                    .unwrap()
+                    .entry(consumer.clone())
+                    .or_default()
+                    .dropped += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    camera
+}
@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+
+use super::{Camera, OwnedFrame};
+
+const CAPTURE_WORKER_STACK_SIZE: usize = 8 * 1024;
+// keeps the worker from spinning while the camera is in standby
+const NO_FRAME_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What the capture worker does when the consumer's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop capturing until the consumer catches up
+    Wait,
+    /// Replace the oldest queued frame, the consumer always gets the latest
+    DropOldest,
+    /// Discard the frame just captured
+    DropNewest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamOptions {
+    fps: Option<f32>,
+    depth: usize,
+    overflow: Overflow,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            fps: None,
+            depth: 1,
+            overflow: Overflow::Wait,
+        }
+    }
+}
+
+impl StreamOptions {
+    /// Caps the capture rate, by default frames come as fast as the sensor sends them
+    pub fn with_fps(mut self, fps: f32) -> Self {
+        self.fps = (fps > 0.0).then_some(fps);
+        self
+    }
+
+    /// How many frames can wait for the consumer
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+struct Queue {
+    frames: VecDeque<OwnedFrame>,
+    waker: Option<Waker>,
+    dropped: u32,
+    // set by whichever side goes away first
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    space: Condvar,
+}
+
+impl Shared {
+    // a panicking worker must still be able to end the stream
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// closes the stream when the worker returns or panics, so the consumer
+// doesn't wait forever
+struct WorkerGuard(Arc<Shared>);
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let mut queue = self.0.queue();
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Frames captured on a blocking worker, see [`Camera::frames`].
+///
+/// The worker is the only one grabbing frames while the stream is alive, and
+/// dropping the stream waits for it to finish the capture in progress.
+/// If the worker stops, the stream ends once the queued frames are taken.
+pub struct FrameStream {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl FrameStream {
+    pub(super) fn start(
+        camera: Arc<Camera<'static>>,
+        options: StreamOptions,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                frames: VecDeque::with_capacity(options.depth),
+                waker: None,
+                dropped: 0,
+                closed: false,
+            }),
+            space: Condvar::new(),
+        });
+
+        let worker_shared = shared.clone();
+        let worker = std::thread::Builder::new()
+            .name("camera-stream".into())
+            .stack_size(CAPTURE_WORKER_STACK_SIZE)
+            .spawn(move || {
+                let _guard = WorkerGuard(worker_shared.clone());
+                capture_worker(camera, worker_shared, options)
+            })?;
+
+        Ok(Self {
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Frames thrown away because the consumer was lagging
+    pub fn dropped(&self) -> u32 {
+        self.shared.queue().dropped
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = OwnedFrame;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OwnedFrame>> {
+        let mut queue = self.shared.queue();
+
+        match queue.frames.pop_front() {
+            Some(frame) => {
+                self.shared.space.notify_one();
+                Poll::Ready(Some(frame))
+            }
+            // the worker is gone and the queue drained
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.shared.queue().closed = true;
+        self.shared.space.notify_one();
+
+        if let Some(worker) = self.worker.take() {
+            worker.join().ok();
+        }
+    }
+}
+
+fn capture_worker(camera: Arc<Camera<'static>>, shared: Arc<Shared>, options: StreamOptions) {
+    let interval = options.fps.map(|fps| Duration::from_secs_f32(1.0 / fps));
+    let mut next_capture = Instant::now();
+
+    loop {
+        if let Some(interval) = interval {
+            let now = Instant::now();
+            if next_capture > now {
+                std::thread::sleep(next_capture - now);
+            }
+            next_capture = next_capture.max(now) + interval;
+        }
+
+        {
+            let queue = shared.queue();
+            // wait before grabbing, so the frame isn't stale once there is room
+            let queue = shared
+                .space
+                .wait_while(queue, |queue| {
+                    options.overflow == Overflow::Wait
+                        && queue.frames.len() >= options.depth
+                        && !queue.closed
+                })
+                .unwrap();
+            if queue.closed {
+                break;
+            }
+        }
+
+        let Some(frame) = camera
+            .get_framebuffer()
+            .and_then(|framebuffer| framebuffer.to_owned().ok())
+        else {
+            std::thread::sleep(NO_FRAME_BACKOFF);
+            continue;
+        };
+
+        let mut queue = shared.queue();
+        if queue.closed {
+            break;
+        }
+
+        if queue.frames.len() >= options.depth {
+            queue.dropped += 1;
+            match options.overflow {
+                Overflow::DropNewest => continue,
+                _ => {
+                    queue.frames.pop_front();
+                }
+            }
+        }
+
+        queue.frames.push_back(frame);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use std::ops::DerefMut;
+use std::time::SystemTime;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::EspError;
+
+#[cfg(target_os = "espidf")]
+use crate::espcam::{CameraSensor, Frame, FrameMeta, HeapBuffer, SensorError};
+use crate::espcam::{DateTime, PixelFormat, SensorModel};
+
+// APP1 length field counts itself, the segment can't be longer than this
+const MAX_APP1_LEN: usize = 0xFFFF;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const EXIF_VERSION: &[u8] = b"0232";
+
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const UNDEFINED: u16 = 7;
+const SRATIONAL: u16 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExifError {
+    #[error("{0} frames can't carry EXIF")]
+    NotJpeg(PixelFormat),
+    #[error("no SOI marker at the start of the data")]
+    MissingSoi,
+    #[error("the EXIF segment would be {0} bytes, more than fits in APP1")]
+    TooLarge(usize),
+    #[cfg(target_os = "espidf")]
+    #[error("could not allocate the output: {0}")]
+    Alloc(#[from] EspError),
+}
+
+/// How a viewer should turn the image to show it upright, the EXIF values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Orientation {
+    #[default]
+    Normal = 1,
+    MirrorHorizontal = 2,
+    Rotate180 = 3,
+    MirrorVertical = 4,
+    MirrorHorizontalRotate270 = 5,
+    Rotate90 = 6,
+    MirrorHorizontalRotate90 = 7,
+    Rotate270 = 8,
+}
+
+/// What goes in the EXIF segment, every field is optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExifData {
+    pub capture_time: Option<SystemTime>,
+    /// Written as the camera model
+    pub device_name: Option<String>,
+    /// Written as the camera make
+    pub sensor_model: Option<SensorModel>,
+    pub orientation: Orientation,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub auto_exposure: Option<bool>,
+    pub auto_white_balance: Option<bool>,
+    /// AE level, in about one EV steps
+    pub exposure_bias: Option<i32>,
+    /// Sensor specific, in line periods
+    pub aec_value: Option<i32>,
+    pub agc_gain: Option<i32>,
+}
+
+impl ExifData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture time, when the clock is synced, and size of a frame
+    #[cfg(target_os = "espidf")]
+    pub fn for_frame(mut self, frame: &impl Frame, meta: &FrameMeta) -> Self {
+        self.capture_time = meta.wall_time();
+        self.width = Some(frame.width() as u32);
+        self.height = Some(frame.height() as u32);
+        self
+    }
+
+    /// Sensor model and the exposure, gain and white balance settings. The
+    /// sensor's mirror and flip already apply to the pixels, so the orientation
+    /// is left alone, set it with [`with_orientation`](Self::with_orientation)
+    /// for a camera that is mounted turned.
+    #[cfg(target_os = "espidf")]
+    pub fn for_sensor(mut self, sensor: &CameraSensor) -> Result<Self, SensorError> {
+        let settings = sensor.settings()?;
+
+        self.sensor_model = Some(sensor.model()?);
+        self.auto_exposure = Some(settings.exposure_ctrl);
+        self.auto_white_balance = Some(settings.whitebal);
+        self.exposure_bias = Some(settings.ae_level);
+        self.aec_value = Some(settings.aec_value);
+        self.agc_gain = Some(settings.agc_gain);
+        Ok(self)
+    }
+
+    pub fn with_device_name(mut self, name: impl Into<String>) -> Self {
+        self.device_name = Some(name.into());
+        self
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// The APP1 payload: the Exif header followed by a big endian TIFF structure
+    pub fn to_app1(&self) -> Vec<u8> {
+        let date_time = self.capture_time.map(format_date_time);
+
+        let mut ifd0 = Ifd::default();
+        if let Some(model) = self.sensor_model {
+            ifd0.ascii(0x010F, model.name());
+        }
+        if let Some(name) = &self.device_name {
+            ifd0.ascii(0x0110, name);
+        }
+        ifd0.short(0x0112, self.orientation as u16);
+        ifd0.ascii(0x0131, "espcam");
+        if let Some(date_time) = &date_time {
+            ifd0.ascii(0x0132, date_time);
+        }
+
+        let mut exif = Ifd::default();
+        exif.entry(UNDEFINED, 0x9000, 4, EXIF_VERSION.to_vec());
+        if let Some(auto) = self.auto_exposure {
+            // manual or normal program
+            exif.short(0x8822, if auto { 2 } else { 1 });
+        }
+        if let Some(date_time) = &date_time {
+            exif.ascii(0x9003, date_time);
+            exif.ascii(0x9004, date_time);
+        }
+        if let Some(bias) = self.exposure_bias {
+            exif.entry(
+                SRATIONAL,
+                0x9204,
+                1,
+                [bias.to_be_bytes(), 1i32.to_be_bytes()].concat(),
+            );
+        }
+        if self.aec_value.is_some() || self.agc_gain.is_some() {
+            let comment = format!(
+                "aec_value={} agc_gain={}",
+                self.aec_value.unwrap_or(-1),
+                self.agc_gain.unwrap_or(-1)
+            );
+            exif.entry(
+                UNDEFINED,
+                0x9286,
+                8 + comment.len() as u32,
+                [&b"ASCII\0\0\0"[..], comment.as_bytes()].concat(),
+            );
+        }
+        if let Some(width) = self.width {
+            exif.long(0xA002, width);
+        }
+        if let Some(height) = self.height {
+            exif.long(0xA003, height);
+        }
+        if let Some(auto) = self.auto_exposure {
+            exif.short(0xA402, if auto { 0 } else { 1 });
+        }
+        if let Some(auto) = self.auto_white_balance {
+            exif.short(0xA403, if auto { 0 } else { 1 });
+        }
+
+        // TIFF header, then IFD0 with a pointer to the Exif IFD right after it
+        let ifd0_offset = 8;
+        ifd0.long(0x8769, 0);
+        let exif_offset = ifd0_offset + ifd0.size();
+        ifd0.set_long(0x8769, exif_offset as u32);
+
+        let mut tiff = Vec::with_capacity(exif_offset + exif.size());
+        tiff.extend_from_slice(b"MM\0\x2A");
+        tiff.extend_from_slice(&(ifd0_offset as u32).to_be_bytes());
+        ifd0.write(&mut tiff);
+        exif.write(&mut tiff);
+
+        [EXIF_HEADER, &tiff[..]].concat()
+    }
+}
+
+/// Copies the JPEG with an APP1 EXIF segment right after SOI, replacing any
+/// EXIF it already had. The image data is left as it is.
+#[cfg(target_os = "espidf")]
+pub fn insert_exif(jpeg: &[u8], exif: &ExifData) -> Result<HeapBuffer, ExifError> {
+    write_exif(jpeg, exif, |len| Ok(HeapBuffer::psram(len)?))
+}
+
+// writes into whatever `alloc` returns for the output length
+fn write_exif<B: DerefMut<Target = [u8]>>(
+    jpeg: &[u8],
+    exif: &ExifData,
+    alloc: impl FnOnce(usize) -> Result<B, ExifError>,
+) -> Result<B, ExifError> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return Err(ExifError::MissingSoi);
+    }
+
+    let payload = exif.to_app1();
+    let segment_len = payload.len() + 2;
+    if segment_len > MAX_APP1_LEN {
+        return Err(ExifError::TooLarge(segment_len));
+    }
+
+    let rest = strip_exif(&jpeg[2..]);
+
+    // SOI, then the APP1 marker and length
+    let head_len = 6 + payload.len();
+    let mut out = alloc(head_len + rest.len())?;
+    let (head, tail) = out.split_at_mut(head_len);
+    head[..2].copy_from_slice(&[0xFF, 0xD8]);
+    head[2..4].copy_from_slice(&[0xFF, 0xE1]);
+    head[4..6].copy_from_slice(&(segment_len as u16).to_be_bytes());
+    head[6..].copy_from_slice(&payload);
+    tail.copy_from_slice(rest);
+
+    Ok(out)
+}
+
+/// [`insert_exif`] on a captured JPEG frame
+#[cfg(target_os = "espidf")]
+pub fn frame_with_exif(frame: &impl Frame, exif: &ExifData) -> Result<HeapBuffer, ExifError> {
+    match frame.format() {
+        PixelFormat::Jpeg => insert_exif(frame.data(), exif),
+        format => Err(ExifError::NotJpeg(format)),
+    }
+}
+
+// drops an EXIF APP1 found at the start, the driver's frames don't have one but re-encoded ones might
+fn strip_exif(data: &[u8]) -> &[u8] {
+    if data.len() >= 4 && data[0] == 0xFF && data[1] == 0xE1 {
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if data.len() >= 2 + len && data[4..].starts_with(EXIF_HEADER) {
+            return &data[2 + len..];
+        }
+    }
+    data
+}
+
+#[derive(Default)]
+struct Ifd {
+    // tag, type, count, value
+    entries: Vec<(u16, u16, u32, Vec<u8>)>,
+}
+
+impl Ifd {
+    fn entry(&mut self, kind: u16, tag: u16, count: u32, value: Vec<u8>) {
+        self.entries.push((tag, kind, count, value));
+    }
+
+    fn ascii(&mut self, tag: u16, value: &str) {
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        self.entry(ASCII, tag, value.len() as u32, value);
+    }
+
+    fn short(&mut self, tag: u16, value: u16) {
+        self.entry(SHORT, tag, 1, value.to_be_bytes().to_vec());
+    }
+
+    fn long(&mut self, tag: u16, value: u32) {
+        self.entry(LONG, tag, 1, value.to_be_bytes().to_vec());
+    }
+
+    fn set_long(&mut self, tag: u16, value: u32) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.0 == tag) {
+            entry.3 = value.to_be_bytes().to_vec();
+        }
+    }
+
+    fn size(&self) -> usize {
+        2 + self.entries.len() * 12 + 4 + self.data_size()
+    }
+
+    // values over 4 bytes go after the entries, on even offsets
+    fn data_size(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.3.len() > 4)
+            .map(|entry| entry.3.len().next_multiple_of(2))
+            .sum()
+    }
+
+    /// Appends the IFD to `tiff`, offsets are counted from the TIFF header
+    fn write(&mut self, tiff: &mut Vec<u8>) {
+        self.entries.sort_by_key(|entry| entry.0);
+
+        let start = tiff.len();
+        let mut data_offset = start + 2 + self.entries.len() * 12 + 4;
+        let mut data = Vec::with_capacity(self.data_size());
+
+        tiff.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+        for (tag, kind, count, value) in &self.entries {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&kind.to_be_bytes());
+            tiff.extend_from_slice(&count.to_be_bytes());
+            if value.len() <= 4 {
+                let mut inline = [0; 4];
+                inline[..value.len()].copy_from_slice(value);
+                tiff.extend_from_slice(&inline);
+            } else {
+                tiff.extend_from_slice(&(data_offset as u32).to_be_bytes());
+                data.extend_from_slice(value);
+                if value.len() % 2 == 1 {
+                    data.push(0);
+                }
+                data_offset += value.len().next_multiple_of(2);
+            }
+        }
+        // no next IFD
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff.extend_from_slice(&data);
+    }
+}
+
+/// `YYYY:MM:DD HH:MM:SS` in UTC, as EXIF wants it
+fn format_date_time(time: SystemTime) -> String {
+    let t = DateTime::utc(time);
+    format!(
+        "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::espcam::validate_jpeg;
+
+    // tag -> (type, count, value bytes)
+    type Tags = BTreeMap<u16, (u16, u32, Vec<u8>)>;
+
+    fn be16(tiff: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([tiff[at], tiff[at + 1]])
+    }
+
+    fn be32(tiff: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(tiff[at..at + 4].try_into().unwrap())
+    }
+
+    fn parse_ifd(tiff: &[u8], offset: usize) -> Tags {
+        let count = be16(tiff, offset) as usize;
+        let mut tags = Tags::new();
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let kind = be16(tiff, entry + 2);
+            let count = be32(tiff, entry + 4);
+            let size = match kind {
+                ASCII | UNDEFINED => 1,
+                SHORT => 2,
+                LONG => 4,
+                SRATIONAL => 8,
+                _ => panic!("unexpected type {kind}"),
+            } * count as usize;
+            let value = if size <= 4 {
+                tiff[entry + 8..entry + 8 + size].to_vec()
+            } else {
+                let at = be32(tiff, entry + 8) as usize;
+                assert_eq!(at % 2, 0, "odd value offset");
+                tiff[at..at + size].to_vec()
+            };
+            tags.insert(be16(tiff, entry), (kind, count, value));
+        }
+        assert_eq!(be32(tiff, offset + 2 + count * 12), 0, "next IFD");
+        tags
+    }
+
+    // IFD0 and the Exif IFD it points to
+    fn parse_app1(app1: &[u8]) -> (Tags, Tags) {
+        let tiff = app1.strip_prefix(EXIF_HEADER).expect("Exif header");
+        assert_eq!(&tiff[..4], b"MM\0\x2A");
+        let ifd0 = parse_ifd(tiff, be32(tiff, 4) as usize);
+        let (_, _, pointer) = &ifd0[&0x8769];
+        let exif = parse_ifd(tiff, be32(pointer, 0) as usize);
+        (ifd0, exif)
+    }
+
+    fn ascii(tags: &Tags, tag: u16) -> &str {
+        let (kind, count, value) = &tags[&tag];
+        assert_eq!((*kind, *count as usize), (ASCII, value.len()));
+        std::str::from_utf8(value.strip_suffix(b"\0").unwrap()).unwrap()
+    }
+
+    fn short(tags: &Tags, tag: u16) -> u16 {
+        let (kind, count, value) = &tags[&tag];
+        assert_eq!((*kind, *count), (SHORT, 1));
+        be16(value, 0)
+    }
+
+    fn long(tags: &Tags, tag: u16) -> u32 {
+        let (kind, count, value) = &tags[&tag];
+        assert_eq!((*kind, *count), (LONG, 1));
+        be32(value, 0)
+    }
+
+    #[test]
+    fn round_trip() {
+        let exif = ExifData {
+            // 2024-02-29 13:45:06 UTC
+            capture_time: Some(UNIX_EPOCH + Duration::from_secs(1_709_214_306)),
+            device_name: Some("porch".into()),
+            sensor_model: None,
+            orientation: Orientation::Rotate180,
+            width: Some(1600),
+            height: Some(1200),
+            auto_exposure: Some(false),
+            auto_white_balance: Some(true),
+            exposure_bias: Some(-2),
+            aec_value: Some(300),
+            agc_gain: Some(5),
+        };
+        let (ifd0, tags) = parse_app1(&exif.to_app1());
+
+        assert_eq!(ascii(&ifd0, 0x0110), "porch");
+        assert_eq!(short(&ifd0, 0x0112), Orientation::Rotate180 as u16);
+        assert_eq!(ascii(&ifd0, 0x0131), "espcam");
+        assert_eq!(ascii(&ifd0, 0x0132), "2024:02:29 13:45:06");
+        assert!(!ifd0.contains_key(&0x010F));
+
+        assert_eq!(tags[&0x9000], (UNDEFINED, 4, b"0232".to_vec()));
+        assert_eq!(short(&tags, 0x8822), 1);
+        assert_eq!(ascii(&tags, 0x9003), "2024:02:29 13:45:06");
+        assert_eq!(ascii(&tags, 0x9004), "2024:02:29 13:45:06");
+        let bias = [(-2i32).to_be_bytes(), 1i32.to_be_bytes()].concat();
+        assert_eq!(tags[&0x9204], (SRATIONAL, 1, bias));
+        let (_, _, comment) = &tags[&0x9286];
+        assert_eq!(&comment[..], b"ASCII\0\0\0aec_value=300 agc_gain=5");
+        assert_eq!(long(&tags, 0xA002), 1600);
+        assert_eq!(long(&tags, 0xA003), 1200);
+        assert_eq!(short(&tags, 0xA402), 1);
+        assert_eq!(short(&tags, 0xA403), 0);
+    }
+
+    #[test]
+    fn empty() {
+        let (ifd0, tags) = parse_app1(&ExifData::new().to_app1());
+
+        assert_eq!(
+            ifd0.keys().copied().collect::<Vec<_>>(),
+            [0x0112, 0x0131, 0x8769]
+        );
+        assert_eq!(short(&ifd0, 0x0112), Orientation::Normal as u16);
+        assert_eq!(tags.keys().copied().collect::<Vec<_>>(), [0x9000]);
+    }
+
+    #[test]
+    fn inserted() {
+        let frame = include_bytes!("../tests/fixtures/frame.jpg");
+        let exif = ExifData::new()
+            .with_device_name("porch")
+            .with_orientation(Orientation::Rotate90);
+
+        let out = write_exif(frame, &exif, |len| Ok(vec![0; len])).unwrap();
+
+        assert_eq!(out[..4], [0xFF, 0xD8, 0xFF, 0xE1]);
+        let len = be16(&out, 4) as usize;
+        let (ifd0, _) = parse_app1(&out[6..4 + len]);
+        assert_eq!(ascii(&ifd0, 0x0110), "porch");
+        assert_eq!(short(&ifd0, 0x0112), Orientation::Rotate90 as u16);
+        // everything after SOI comes through byte for byte
+        assert_eq!(out[4 + len..], frame[2..]);
+        assert_eq!(validate_jpeg(&out), Ok(out.len()));
+
+        assert!(matches!(
+            write_exif(&frame[2..], &exif, |len| Ok(vec![0; len])),
+            Err(ExifError::MissingSoi)
+        ));
+    }
+
+    #[test]
+    fn strips_old_exif() {
+        let old = [&[0xFF, 0xE1, 0x00, 0x0A][..], EXIF_HEADER, &[0, 0]].concat();
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9];
+        assert_eq!(strip_exif(&[&old[..], &scan].concat()), scan);
+        assert_eq!(strip_exif(&scan), scan);
+    }
+}
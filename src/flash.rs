@@ -0,0 +1,355 @@
+use std::collections::VecDeque;
+use std::ffi::c_void;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::gpio::{AnyOutputPin, Pin};
+use esp_idf_sys::*;
+
+use crate::espcam::{Camera, CameraConfig, FrameBuffer};
+
+pub const BRIGHTNESS_RANGE: RangeInclusive<u8> = 0..=100;
+
+// the camera clock is on the low speed LEDC block too
+const SPEED_MODE: ledc_mode_t = ledc_mode_t_LEDC_LOW_SPEED_MODE;
+const MAX_DUTY: u32 = (1 << 8) - 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FlashError {
+    #[error("LEDC error: {0}")]
+    Esp(#[from] EspError),
+    #[error("the flash LEDC timer or channel is already used by the camera clock")]
+    LedcCollision,
+    #[error("brightness {0} is out of range")]
+    InvalidBrightness(u8),
+    #[error("the flash has been on too long, let it cool down")]
+    ThermalLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashConfig {
+    ledc_timer: ledc_timer_t,
+    ledc_channel: ledc_channel_t,
+    frequency_hz: u32,
+    capture_brightness: u8,
+    max_brightness: u8,
+    pre_flash: Duration,
+    thermal_budget: Duration,
+    thermal_window: Duration,
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        Self {
+            ledc_timer: ledc_timer_t_LEDC_TIMER_1,
+            ledc_channel: ledc_channel_t_LEDC_CHANNEL_1,
+            frequency_hz: 5000,
+            capture_brightness: 100,
+            max_brightness: 100,
+            pre_flash: Duration::from_millis(200),
+            thermal_budget: Duration::from_secs(3),
+            thermal_window: Duration::from_secs(30),
+        }
+    }
+}
+
+impl FlashConfig {
+    /// Must differ from the ones in [`CameraConfig::with_ledc`]
+    pub fn with_ledc(mut self, timer: ledc_timer_t, channel: ledc_channel_t) -> Self {
+        self.ledc_timer = timer;
+        self.ledc_channel = channel;
+        self
+    }
+
+    pub fn with_frequency_hz(mut self, frequency_hz: u32) -> Self {
+        self.frequency_hz = frequency_hz;
+        self
+    }
+
+    pub fn with_capture_brightness(mut self, brightness: u8) -> Self {
+        self.capture_brightness = brightness;
+        self
+    }
+
+    /// Caps every brightness the flash is set to
+    pub fn with_max_brightness(mut self, brightness: u8) -> Self {
+        self.max_brightness = brightness;
+        self
+    }
+
+    /// How long the flash is on before a capture, for auto exposure to adapt
+    pub fn with_pre_flash(mut self, pre_flash: Duration) -> Self {
+        self.pre_flash = pre_flash;
+        self
+    }
+
+    /// The flash refuses to turn on once it has been on for `budget` within
+    /// the last `window`, counted at full brightness. A flash left on is
+    /// turned off by a timer when it runs out of budget.
+    pub fn with_thermal_limit(mut self, budget: Duration, window: Duration) -> Self {
+        self.thermal_budget = budget;
+        self.thermal_window = window;
+        self
+    }
+
+    pub fn ledc(&self) -> (ledc_timer_t, ledc_channel_t) {
+        (self.ledc_timer, self.ledc_channel)
+    }
+
+    pub fn capture_brightness(&self) -> u8 {
+        self.capture_brightness
+    }
+
+    pub fn pre_flash(&self) -> Duration {
+        self.pre_flash
+    }
+
+    pub fn validate(&self, camera: &CameraConfig) -> Result<(), FlashError> {
+        let (camera_timer, camera_channel) = camera.ledc();
+        if self.ledc_timer == camera_timer || self.ledc_channel == camera_channel {
+            return Err(FlashError::LedcCollision);
+        }
+        for brightness in [self.capture_brightness, self.max_brightness] {
+            if !BRIGHTNESS_RANGE.contains(&brightness) {
+                return Err(FlashError::InvalidBrightness(brightness));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many times to flash and for how long, e.g. to report a status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlinkPattern {
+    pub brightness: u8,
+    pub on: Duration,
+    pub off: Duration,
+    pub count: u32,
+}
+
+impl BlinkPattern {
+    pub const READY: Self = Self::code(1);
+    pub const ERROR: Self = Self::code(5);
+
+    /// `count` short blinks
+    pub const fn code(count: u32) -> Self {
+        Self {
+            brightness: 100,
+            on: Duration::from_millis(1),
+            off: Duration::from_millis(80),
+            count,
+        }
+    }
+}
+
+/// The white illumination LED, dimmed through LEDC PWM.
+///
+/// ```ignore
+/// let mut pins = espcam::camera_pins!(AiThinker, peripherals.pins);
+/// let mut flash = Flash::new(pins.flash_led.take().unwrap(), FlashConfig::default(), &camera_config)?;
+/// let frame = flash.capture(&camera)?;
+/// ```
+pub struct Flash<'d> {
+    _pin: AnyOutputPin<'d>,
+    config: FlashConfig,
+    brightness: u8,
+    on_since: Option<Instant>,
+    // when the cutoff timer turns the flash off, Some while it's on
+    cut_off_at: Option<Instant>,
+    cutoff: esp_timer_handle_t,
+    // past on periods weighted by brightness, for the thermal limit
+    history: VecDeque<(Instant, Duration)>,
+}
+
+impl<'d> Flash<'d> {
+    pub fn new(
+        pin: AnyOutputPin<'d>,
+        config: FlashConfig,
+        camera: &CameraConfig,
+    ) -> Result<Self, FlashError> {
+        config.validate(camera)?;
+
+        let timer = ledc_timer_config_t {
+            speed_mode: SPEED_MODE,
+            duty_resolution: ledc_timer_bit_t_LEDC_TIMER_8_BIT,
+            timer_num: config.ledc_timer,
+            freq_hz: config.frequency_hz,
+            ..Default::default()
+        };
+        esp!(unsafe { ledc_timer_config(&timer) })?;
+
+        let channel = ledc_channel_config_t {
+            gpio_num: pin.pin() as _,
+            speed_mode: SPEED_MODE,
+            channel: config.ledc_channel,
+            timer_sel: config.ledc_timer,
+            duty: 0,
+            hpoint: 0,
+            ..Default::default()
+        };
+        esp!(unsafe { ledc_channel_config(&channel) })?;
+
+        let cutoff_args = esp_timer_create_args_t {
+            callback: Some(thermal_cutoff),
+            arg: config.ledc_channel as usize as *mut c_void,
+            dispatch_method: esp_timer_dispatch_t_ESP_TIMER_TASK,
+            name: c"flash-cutoff".as_ptr(),
+            skip_unhandled_events: false,
+        };
+        let mut cutoff = std::ptr::null_mut();
+        esp!(unsafe { esp_timer_create(&cutoff_args, &mut cutoff) })?;
+
+        Ok(Self {
+            _pin: pin,
+            config,
+            brightness: 0,
+            on_since: None,
+            cut_off_at: None,
+            cutoff,
+            history: VecDeque::new(),
+        })
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<(), FlashError> {
+        if !BRIGHTNESS_RANGE.contains(&brightness) {
+            return Err(FlashError::InvalidBrightness(brightness));
+        }
+        let brightness = brightness.min(self.config.max_brightness);
+
+        // not running is fine, the flash may be off or cut off already
+        unsafe { esp_timer_stop(self.cutoff) };
+        self.end_on_period();
+
+        let heat = self.heat();
+        if brightness > 0 && heat >= self.config.thermal_budget {
+            self.set_duty(0)?;
+            self.brightness = 0;
+            return Err(FlashError::ThermalLimit);
+        }
+
+        self.set_duty(brightness as u32 * MAX_DUTY / 100)?;
+        self.brightness = brightness;
+        if brightness > 0 {
+            let now = Instant::now();
+            let left = (self.config.thermal_budget - heat).div_f32(brightness as f32 / 100.0);
+            esp!(unsafe { esp_timer_start_once(self.cutoff, left.as_micros() as u64) })?;
+            self.on_since = Some(now);
+            self.cut_off_at = Some(now + left);
+        }
+        Ok(())
+    }
+
+    pub fn on(&mut self) -> Result<(), FlashError> {
+        self.set_brightness(self.config.capture_brightness)
+    }
+
+    pub fn off(&mut self) -> Result<(), FlashError> {
+        self.set_brightness(0)
+    }
+
+    /// 0 once the thermal cutoff has turned the flash off
+    pub fn brightness(&self) -> u8 {
+        if self.cut_off_at.is_some_and(|at| Instant::now() >= at) {
+            0
+        } else {
+            self.brightness
+        }
+    }
+
+    /// Lights the scene for the pre-flash time, runs `f` and turns off again.
+    /// Past the thermal limit `f` runs without the flash.
+    pub fn with_flash<R>(&mut self, f: impl FnOnce() -> R) -> Result<R, FlashError> {
+        if self.light()? {
+            std::thread::sleep(self.config.pre_flash);
+        }
+
+        let result = f();
+
+        self.off()?;
+        Ok(result)
+    }
+
+    /// [`Camera::capture_fresh`] with the flash on, the pre-flash is waited
+    /// out as the camera's settle time. Past the thermal limit the frame is
+    /// captured without the flash.
+    pub fn capture<'c>(
+        &mut self,
+        camera: &'c Camera<'_>,
+    ) -> Result<Option<FrameBuffer<'c>>, FlashError> {
+        if self.light()? {
+            camera.settle(self.config.pre_flash);
+        }
+
+        let frame = camera.capture_fresh();
+
+        self.off()?;
+        Ok(frame)
+    }
+
+    pub fn blink(&mut self, pattern: &BlinkPattern) -> Result<(), FlashError> {
+        for _ in 0..pattern.count {
+            self.set_brightness(pattern.brightness)?;
+            std::thread::sleep(pattern.on);
+            self.off()?;
+            std::thread::sleep(pattern.off);
+        }
+        Ok(())
+    }
+
+    /// Turns on for a capture, false when the thermal limit keeps it off
+    fn light(&mut self) -> Result<bool, FlashError> {
+        match self.on() {
+            Ok(()) => Ok(true),
+            Err(FlashError::ThermalLimit) => {
+                log::warn!("flash is too hot, capturing without it");
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn set_duty(&self, duty: u32) -> Result<(), EspError> {
+        esp!(unsafe { ledc_set_duty(SPEED_MODE, self.config.ledc_channel, duty) })?;
+        esp!(unsafe { ledc_update_duty(SPEED_MODE, self.config.ledc_channel) })
+    }
+
+    fn end_on_period(&mut self) {
+        if let Some(since) = self.on_since.take() {
+            // the cutoff timer may have turned it off earlier
+            let now = Instant::now();
+            let end = self.cut_off_at.take().map_or(now, |at| at.min(now));
+            let weighted = end
+                .saturating_duration_since(since)
+                .mul_f32(self.brightness as f32 / 100.0);
+            self.history.push_back((end, weighted));
+        }
+    }
+
+    fn heat(&mut self) -> Duration {
+        let now = Instant::now();
+        let window = self.config.thermal_window;
+        self.history
+            .retain(|(end, _)| now.duration_since(*end) < window);
+        self.history.iter().map(|(_, on)| *on).sum()
+    }
+}
+
+impl Drop for Flash<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            esp_timer_stop(self.cutoff);
+            esp_timer_delete(self.cutoff);
+            ledc_stop(SPEED_MODE, self.config.ledc_channel, 0);
+        }
+    }
+}
+
+// esp_timer handles can be used from any task
+unsafe impl Send for Flash<'_> {}
+
+// runs on the esp_timer task, `arg` is the LEDC channel
+unsafe extern "C" fn thermal_cutoff(arg: *mut c_void) {
+    let channel = arg as usize as ledc_channel_t;
+    ledc_set_duty(SPEED_MODE, channel, 0);
+    ledc_update_duty(SPEED_MODE, channel);
+}
@@ -1,4 +1,20 @@
+// the hardware independent modules also build for the host, where their tests
+// run, without the code on the target that uses all of them
+#![cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+
+#[cfg(target_os = "espidf")]
 pub mod ble;
 pub mod config;
+pub mod convert;
 pub mod espcam;
+pub mod exif;
+#[cfg(target_os = "espidf")]
+pub mod flash;
+pub mod motion;
+#[cfg(target_os = "espidf")]
+pub mod overlay;
+#[cfg(target_os = "espidf")]
+pub mod store;
+pub mod timelapse;
+#[cfg(target_os = "espidf")]
 pub mod wifi_handler;
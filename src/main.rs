@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use esp_idf_svc::hal::peripherals::Peripherals;
-use espcam::espcam::Camera;
+use espcam::espcam::{Camera, CameraConfig, FrameSize, PixelFormat};
 
 fn main() -> Result<()> {
     esp_idf_svc::sys::link_patches();
@@ -10,23 +10,8 @@ fn main() -> Result<()> {
     let peripherals = Peripherals::take().unwrap();
 
     let camera = Camera::new(
-        peripherals.pins.gpio32,
-        peripherals.pins.gpio0,
-        peripherals.pins.gpio5,
-        peripherals.pins.gpio18,
-        peripherals.pins.gpio19,
-        peripherals.pins.gpio21,
-        peripherals.pins.gpio36,
-        peripherals.pins.gpio39,
-        peripherals.pins.gpio34,
-        peripherals.pins.gpio35,
-        peripherals.pins.gpio25,
-        peripherals.pins.gpio23,
-        peripherals.pins.gpio22,
-        peripherals.pins.gpio26,
-        peripherals.pins.gpio27,
-        esp_idf_sys::camera::pixformat_t_PIXFORMAT_JPEG,
-        esp_idf_sys::camera::framesize_t_FRAMESIZE_UXGA,
+        espcam::camera_pins!(AiThinker, peripherals.pins),
+        CameraConfig::new(PixelFormat::Jpeg, FrameSize::Uxga),
     )
     .unwrap();
 
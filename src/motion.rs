@@ -0,0 +1,9 @@
+mod scene;
+
+#[cfg(target_os = "espidf")]
+mod detector;
+
+pub use scene::{MotionBox, MotionConfig, Zone};
+
+#[cfg(target_os = "espidf")]
+pub use detector::{MotionDetector, MotionError, MotionEvent, MotionMonitor};
@@ -0,0 +1,294 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use esp_idf_sys::{camera, EspError};
+use serde::{Deserialize, Serialize};
+
+use crate::convert::luma;
+use crate::espcam::{
+    jpeg_size, CameraService, CorruptJpeg, Frame, FrameMeta, HeapBuffer, PixelFormat, ServiceError,
+};
+
+use super::scene::Scene;
+use super::{MotionBox, MotionConfig};
+
+const MONITOR_STACK_SIZE: usize = 8 * 1024;
+// how often the monitor checks whether it should stop while no frames come in
+const MONITOR_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, thiserror::Error)]
+pub enum MotionError {
+    #[error("{0} frames can't be analysed")]
+    UnsupportedFormat(PixelFormat),
+    #[error("frame buffer holds {actual} bytes, {expected} expected")]
+    BufferTooShort { expected: usize, actual: usize },
+    #[error("could not allocate the decoded frame: {0}")]
+    Alloc(#[from] EspError),
+    #[error("jpeg decoding failed")]
+    Decode,
+    #[error("{0}")]
+    Corrupt(#[from] CorruptJpeg),
+    #[error("the jpeg header says {header:?} pixels, the frame {frame:?}")]
+    SizeMismatch {
+        header: (usize, usize),
+        frame: (usize, usize),
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MotionEvent {
+    /// Capture time since boot of the frame the motion was seen in
+    pub timestamp: Duration,
+    pub sequence: u64,
+    /// Largest first
+    pub boxes: Vec<MotionBox>,
+    /// Names of the zones the boxes touch
+    pub zones: Vec<String>,
+}
+
+/// Compares frames against a running average of the scene.
+///
+/// Frames are averaged down to a small luma grid, cells that differ from the
+/// background by more than the sensitivity allows are grouped into blobs and
+/// blobs over the minimum area are reported.
+pub struct MotionDetector {
+    config: MotionConfig,
+    scene: Scene,
+    subscribers: Vec<SyncSender<MotionEvent>>,
+}
+
+impl MotionDetector {
+    pub fn new(config: MotionConfig) -> Self {
+        Self {
+            config,
+            scene: Scene::default(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MotionConfig {
+        &self.config
+    }
+
+    /// Events from now on, at most `depth` queued; the ones that don't fit are dropped
+    pub fn subscribe(&mut self, depth: usize) -> Receiver<MotionEvent> {
+        let (sender, events) = mpsc::sync_channel(depth.max(1));
+        self.subscribers.push(sender);
+        events
+    }
+
+    /// Learns the background again from the next frames, e.g. after moving the camera
+    pub fn reset(&mut self) {
+        self.scene.reset();
+    }
+
+    /// Analyses a JPEG, RGB565, YUV422, GRAYSCALE or RGB888 frame. JPEG frames
+    /// are decoded at reduced size, which is a lot cheaper than a full decode,
+    /// and refused when their header disagrees with the frame's size.
+    pub fn process(
+        &mut self,
+        frame: &impl Frame,
+        meta: &FrameMeta,
+    ) -> Result<Option<MotionEvent>, MotionError> {
+        let (width, height, format) = (frame.width(), frame.height(), frame.format());
+
+        let luma_frame = match format {
+            PixelFormat::Jpeg => {
+                // the decoder writes as many pixels as the header says, it has to match
+                let header = jpeg_size(frame.data())?;
+                if header != (width, height) {
+                    return Err(MotionError::SizeMismatch {
+                        header,
+                        frame: (width, height),
+                    });
+                }
+                let (scale, factor) = jpeg_scale(width, self.config.grid_width());
+                // partly covered blocks at the edges are decoded too
+                let (width, height) = (width.div_ceil(factor), height.div_ceil(factor));
+                let mut decoded = HeapBuffer::psram(width * height * 2)?;
+                let ok = unsafe {
+                    camera::jpg2rgb565(
+                        frame.data().as_ptr(),
+                        frame.data().len(),
+                        decoded.as_mut_ptr(),
+                        scale,
+                    )
+                };
+                if !ok {
+                    return Err(MotionError::Decode);
+                }
+                // unlike the sensor, the decoder writes RGB565 low byte first
+                let pixels = decoded
+                    .chunks_exact(2)
+                    .map(|pixel| rgb565_luma(u16::from_le_bytes([pixel[0], pixel[1]])))
+                    .collect();
+                LumaFrame::new(pixels, width, height)
+            }
+            _ => LumaFrame::from_raw(frame.data(), width, height, format)?,
+        };
+
+        Ok(self.process_luma(&luma_frame.data, luma_frame.width, luma_frame.height, meta))
+    }
+
+    /// Analyses a frame given as one brightness byte per pixel, row by row
+    pub fn process_luma(
+        &mut self,
+        luma: &[u8],
+        width: usize,
+        height: usize,
+        meta: &FrameMeta,
+    ) -> Option<MotionEvent> {
+        let (boxes, zones) = self.scene.update(&self.config, luma, width, height)?;
+
+        let event = MotionEvent {
+            timestamp: meta.timestamp,
+            sequence: meta.sequence,
+            boxes,
+            zones,
+        };
+        self.subscribers
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        Some(event)
+    }
+}
+
+/// Runs a [`MotionDetector`] on frames streamed from a [`CameraService`].
+///
+/// ```ignore
+/// let monitor = MotionMonitor::start(&service, MotionDetector::new(MotionConfig::default()))?;
+/// let events = monitor.subscribe(4);
+/// for event in events {
+///     log::info!("motion in {:?}", event.zones);
+/// }
+/// ```
+pub struct MotionMonitor {
+    detector: Arc<Mutex<MotionDetector>>,
+    running: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MotionMonitor {
+    pub fn start(service: &CameraService, detector: MotionDetector) -> Result<Self, ServiceError> {
+        // one frame queued at most, the detector always looks at a recent one
+        let frames = service.subscribe("motion", 1)?;
+        let detector = Arc::new(Mutex::new(detector));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let (task_detector, task_running) = (detector.clone(), running.clone());
+        let task = std::thread::Builder::new()
+            .name("motion".into())
+            .stack_size(MONITOR_STACK_SIZE)
+            .spawn(move || {
+                while task_running.load(Ordering::SeqCst) {
+                    let Some(frame) = frames.recv_timeout(MONITOR_POLL) else {
+                        continue;
+                    };
+                    if let Err(err) = task_detector.lock().unwrap().process(&*frame, frame.meta()) {
+                        log::warn!(
+                            "motion detection failed on frame {}: {}",
+                            frame.sequence(),
+                            err
+                        );
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            detector,
+            running,
+            task: Some(task),
+        })
+    }
+
+    pub fn subscribe(&self, depth: usize) -> Receiver<MotionEvent> {
+        self.detector.lock().unwrap().subscribe(depth)
+    }
+
+    pub fn reset(&self) {
+        self.detector.lock().unwrap().reset();
+    }
+}
+
+impl Drop for MotionMonitor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(task) = self.task.take() {
+            task.join().ok();
+        }
+    }
+}
+
+// one brightness byte per pixel
+struct LumaFrame {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl LumaFrame {
+    fn new(data: Vec<u8>, width: usize, height: usize) -> Self {
+        Self {
+            data,
+            width,
+            height,
+        }
+    }
+
+    fn from_raw(
+        data: &[u8],
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Result<Self, MotionError> {
+        let bytes_per_pixel = match format {
+            PixelFormat::Grayscale
+            | PixelFormat::Rgb565
+            | PixelFormat::Yuv422
+            | PixelFormat::Rgb888 => format.bytes_per_pixel().unwrap_or(1),
+            _ => return Err(MotionError::UnsupportedFormat(format)),
+        };
+        let expected = width * height * bytes_per_pixel;
+        if data.len() < expected {
+            return Err(MotionError::BufferTooShort {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        let pixels = data[..expected]
+            .chunks_exact(bytes_per_pixel)
+            .map(|pixel| match format {
+                // Y comes first in every pair of YUYV
+                PixelFormat::Grayscale | PixelFormat::Yuv422 => pixel[0],
+                PixelFormat::Rgb565 => rgb565_luma(u16::from_be_bytes([pixel[0], pixel[1]])),
+                _ => luma(pixel[2], pixel[1], pixel[0]),
+            })
+            .collect();
+        Ok(Self::new(pixels, width, height))
+    }
+}
+
+fn rgb565_luma(raw: u16) -> u8 {
+    let r = (raw >> 8) as u8 & 0xF8;
+    let g = (raw >> 3) as u8 & 0xFC;
+    let b = (raw << 3) as u8;
+    luma(r, g, b)
+}
+
+/// Largest decoder scale that still leaves at least `grid_width` pixels across
+fn jpeg_scale(width: usize, grid_width: usize) -> (camera::jpg_scale_t, usize) {
+    [
+        (camera::jpg_scale_t_JPG_SCALE_8X, 8),
+        (camera::jpg_scale_t_JPG_SCALE_4X, 4),
+        (camera::jpg_scale_t_JPG_SCALE_2X, 2),
+    ]
+    .into_iter()
+    .find(|(_, factor)| width / factor >= grid_width)
+    .unwrap_or((camera::jpg_scale_t_JPG_SCALE_NONE, 1))
+}
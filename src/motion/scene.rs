@@ -0,0 +1,563 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "espidf")]
+use crate::overlay::Rect;
+
+// more of the frame than this changing at once is a lighting change, not motion
+const LIGHTING_CHANGE: f32 = 0.8;
+// changed cells are blended in slower, so a moving object doesn't become background
+const CHANGED_RATE_DIVISOR: f32 = 10.0;
+
+/// A named part of the frame to watch, in normalised coordinates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Zone {
+    pub fn new(name: impl Into<String>, x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            name: name.into(),
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub(super) fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotionConfig {
+    grid_width: usize,
+    sensitivity: u8,
+    min_area: f32,
+    learning_rate: f32,
+    warmup_frames: u32,
+    zones: Vec<Zone>,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            grid_width: 64,
+            sensitivity: 50,
+            min_area: 0.005,
+            learning_rate: 0.05,
+            warmup_frames: 5,
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl MotionConfig {
+    /// Cells across the grid frames are averaged down to, rows follow the
+    /// aspect ratio
+    pub fn with_grid_width(mut self, grid_width: usize) -> Self {
+        self.grid_width = grid_width.max(1);
+        self
+    }
+
+    /// From 1 to 100, higher reacts to smaller brightness changes
+    pub fn with_sensitivity(mut self, sensitivity: u8) -> Self {
+        self.sensitivity = sensitivity.clamp(1, 100);
+        self
+    }
+
+    /// Smallest moving blob reported, as a fraction of the frame
+    pub fn with_min_area(mut self, min_area: f32) -> Self {
+        self.min_area = min_area;
+        self
+    }
+
+    /// How fast the background follows the scene, from 0.0 to 1.0 per frame
+    pub fn with_learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Frames to learn the background from before reporting anything
+    pub fn with_warmup_frames(mut self, warmup_frames: u32) -> Self {
+        self.warmup_frames = warmup_frames;
+        self
+    }
+
+    /// Only motion inside the zones is reported, without any the whole frame is watched
+    pub fn zone(mut self, zone: Zone) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    pub fn grid_width(&self) -> usize {
+        self.grid_width
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// Brightness difference from the background that counts as a change
+    pub(super) fn threshold(&self) -> f32 {
+        8.0 + (100 - self.sensitivity) as f32 * 0.56
+    }
+}
+
+/// Bounding box of a moving blob, in normalised coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MotionBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Changed part of the frame inside the box
+    pub area: f32,
+}
+
+#[cfg(target_os = "espidf")]
+impl MotionBox {
+    /// The box in pixels of a `width` x `height` frame, e.g. to draw it with an
+    /// [`Overlay`](crate::overlay::Overlay)
+    pub fn to_rect(self, width: usize, height: usize) -> Rect {
+        let (width, height) = (width as f32, height as f32);
+        Rect::new(
+            (self.x * width) as u32,
+            (self.y * height) as u32,
+            (self.width * width).ceil() as u32,
+            (self.height * height).ceil() as u32,
+        )
+    }
+}
+
+/// The running average of the scene on a small luma grid, and the blobs of
+/// cells that differ from it
+#[derive(Default)]
+pub(super) struct Scene {
+    background: Vec<f32>,
+    grid: (usize, usize),
+    // cells inside a zone, all of them without zones
+    watched: Vec<bool>,
+    frames: u32,
+}
+
+impl Scene {
+    pub(super) fn reset(&mut self) {
+        self.background.clear();
+        self.frames = 0;
+    }
+
+    /// Moving boxes, largest first, and the names of the zones they touch.
+    /// `None` while learning the background or when nothing moved.
+    pub(super) fn update(
+        &mut self,
+        config: &MotionConfig,
+        luma: &[u8],
+        width: usize,
+        height: usize,
+    ) -> Option<(Vec<MotionBox>, Vec<String>)> {
+        if width == 0 || height == 0 || luma.len() < width * height {
+            return None;
+        }
+
+        let grid = self.downscale(config, luma, width, height);
+        let (grid_width, grid_height) = self.grid;
+        let cells = grid.len();
+
+        // a new frame size or a reset starts over
+        if self.background.len() != cells {
+            self.background = grid.iter().map(|&value| value as f32).collect();
+            self.frames = 1;
+            return None;
+        }
+        self.frames = self.frames.saturating_add(1);
+
+        let threshold = config.threshold();
+        let changed: Vec<bool> = grid
+            .iter()
+            .zip(&self.background)
+            .map(|(&value, &background)| (value as f32 - background).abs() > threshold)
+            .collect();
+
+        let rate = config.learning_rate;
+        for ((background, &value), &changed) in self.background.iter_mut().zip(&grid).zip(&changed)
+        {
+            let rate = if changed {
+                rate / CHANGED_RATE_DIVISOR
+            } else {
+                rate
+            };
+            *background += (value as f32 - *background) * rate;
+        }
+
+        let changed_cells = changed.iter().filter(|&&changed| changed).count();
+        if changed_cells as f32 > cells as f32 * LIGHTING_CHANGE {
+            self.background = grid.iter().map(|&value| value as f32).collect();
+            return None;
+        }
+        if self.frames <= config.warmup_frames {
+            return None;
+        }
+
+        let mut boxes = Vec::new();
+        let mut zones: Vec<String> = Vec::new();
+        let min_cells = config.min_area * cells as f32;
+        for blob in blobs(&changed, &self.watched, grid_width, grid_height) {
+            if (blob.cells.len() as f32) < min_cells.max(1.0) {
+                continue;
+            }
+
+            for &cell in &blob.cells {
+                let x = ((cell % grid_width) as f32 + 0.5) / grid_width as f32;
+                let y = ((cell / grid_width) as f32 + 0.5) / grid_height as f32;
+                for zone in &config.zones {
+                    if zone.contains(x, y) && !zones.contains(&zone.name) {
+                        zones.push(zone.name.clone());
+                    }
+                }
+            }
+
+            boxes.push(MotionBox {
+                x: blob.min_x as f32 / grid_width as f32,
+                y: blob.min_y as f32 / grid_height as f32,
+                width: (blob.max_x - blob.min_x + 1) as f32 / grid_width as f32,
+                height: (blob.max_y - blob.min_y + 1) as f32 / grid_height as f32,
+                area: blob.cells.len() as f32 / cells as f32,
+            });
+        }
+        if boxes.is_empty() {
+            return None;
+        }
+        boxes.sort_by(|a, b| b.area.total_cmp(&a.area));
+        Some((boxes, zones))
+    }
+
+    // averages the luma down to the grid, updating its size and the watched cells
+    fn downscale(
+        &mut self,
+        config: &MotionConfig,
+        luma: &[u8],
+        width: usize,
+        height: usize,
+    ) -> Vec<u8> {
+        let grid_width = config.grid_width.min(width).max(1);
+        let grid_height = (height * grid_width / width.max(1)).max(1);
+
+        if self.grid != (grid_width, grid_height) {
+            self.grid = (grid_width, grid_height);
+            self.background.clear();
+            self.watched = (0..grid_width * grid_height)
+                .map(|cell| {
+                    let x = ((cell % grid_width) as f32 + 0.5) / grid_width as f32;
+                    let y = ((cell / grid_width) as f32 + 0.5) / grid_height as f32;
+                    config.zones.is_empty() || config.zones.iter().any(|zone| zone.contains(x, y))
+                })
+                .collect();
+        }
+
+        let mut grid = Vec::with_capacity(grid_width * grid_height);
+        for grid_y in 0..grid_height {
+            let (y_start, y_end) = (
+                grid_y * height / grid_height,
+                (grid_y + 1) * height / grid_height,
+            );
+            for grid_x in 0..grid_width {
+                let (x_start, x_end) = (
+                    grid_x * width / grid_width,
+                    (grid_x + 1) * width / grid_width,
+                );
+
+                let mut sum = 0u32;
+                for y in y_start..y_end {
+                    sum += luma[y * width + x_start..y * width + x_end]
+                        .iter()
+                        .map(|&value| value as u32)
+                        .sum::<u32>();
+                }
+                let count = ((y_end - y_start) * (x_end - x_start)).max(1) as u32;
+                grid.push((sum / count) as u8);
+            }
+        }
+        grid
+    }
+}
+
+struct Blob {
+    cells: Vec<usize>,
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+/// Groups the changed, watched cells that touch, diagonals included
+fn blobs(changed: &[bool], watched: &[bool], width: usize, height: usize) -> Vec<Blob> {
+    let mut seen = vec![false; changed.len()];
+    let mut blobs = Vec::new();
+
+    for start in 0..changed.len() {
+        if seen[start] || !changed[start] || !watched[start] {
+            continue;
+        }
+
+        let mut blob = Blob {
+            cells: Vec::new(),
+            min_x: usize::MAX,
+            min_y: usize::MAX,
+            max_x: 0,
+            max_y: 0,
+        };
+        let mut stack = vec![start];
+        seen[start] = true;
+
+        while let Some(cell) = stack.pop() {
+            let (x, y) = (cell % width, cell / width);
+            blob.cells.push(cell);
+            blob.min_x = blob.min_x.min(x);
+            blob.min_y = blob.min_y.min(y);
+            blob.max_x = blob.max_x.max(x);
+            blob.max_y = blob.max_y.max(y);
+
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = ny * width + nx;
+                    if !seen[neighbour] && changed[neighbour] && watched[neighbour] {
+                        seen[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        blobs.push(blob);
+    }
+    blobs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 x 12 grid cells of 4 x 4 pixels
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+    const CELL: usize = 4;
+
+    fn config() -> MotionConfig {
+        MotionConfig::default()
+            .with_grid_width(WIDTH / CELL)
+            .with_warmup_frames(2)
+            .with_min_area(0.0)
+    }
+
+    /// A flat frame with bright rectangles, given in grid cells as x, y, width, height
+    fn frame(background: u8, objects: &[(usize, usize, usize, usize)]) -> Vec<u8> {
+        let mut luma = vec![background; WIDTH * HEIGHT];
+        for &(x, y, width, height) in objects {
+            for row in y * CELL..(y + height) * CELL {
+                luma[row * WIDTH + x * CELL..row * WIDTH + (x + width) * CELL].fill(255);
+            }
+        }
+        luma
+    }
+
+    fn update(scene: &mut Scene, config: &MotionConfig, luma: &[u8]) -> Option<Vec<MotionBox>> {
+        scene
+            .update(config, luma, WIDTH, HEIGHT)
+            .map(|(boxes, _)| boxes)
+    }
+
+    fn learned(config: &MotionConfig) -> Scene {
+        let mut scene = Scene::default();
+        for _ in 0..=config.warmup_frames {
+            assert_eq!(update(&mut scene, config, &frame(50, &[])), None);
+        }
+        scene
+    }
+
+    #[test]
+    fn static_scene() {
+        let config = config();
+        let mut scene = learned(&config);
+        for _ in 0..20 {
+            assert_eq!(update(&mut scene, &config, &frame(50, &[])), None);
+        }
+    }
+
+    #[test]
+    fn slow_brightness_drift() {
+        // the background keeps up with a change of one level per frame
+        let config = config();
+        let mut scene = learned(&config);
+        for level in 50..150 {
+            assert_eq!(update(&mut scene, &config, &frame(level, &[])), None);
+        }
+    }
+
+    #[test]
+    fn moving_object() {
+        let config = config();
+        let mut scene = learned(&config);
+
+        let boxes = update(&mut scene, &config, &frame(50, &[(4, 2, 4, 4)])).unwrap();
+        assert_eq!(
+            boxes,
+            [MotionBox {
+                x: 4.0 / 16.0,
+                y: 2.0 / 12.0,
+                width: 4.0 / 16.0,
+                height: 4.0 / 12.0,
+                area: 16.0 / 192.0,
+            }]
+        );
+        #[cfg(target_os = "espidf")]
+        assert_eq!(boxes[0].to_rect(WIDTH, HEIGHT), Rect::new(16, 8, 16, 16));
+
+        // where it was is background again
+        let boxes = update(&mut scene, &config, &frame(50, &[(10, 2, 4, 4)])).unwrap();
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].x, 10.0 / 16.0);
+    }
+
+    #[test]
+    fn nothing_during_warmup() {
+        let config = config();
+        let mut scene = Scene::default();
+        assert_eq!(update(&mut scene, &config, &frame(50, &[])), None);
+        assert_eq!(
+            update(&mut scene, &config, &frame(50, &[(0, 0, 4, 4)])),
+            None
+        );
+        assert!(update(&mut scene, &config, &frame(50, &[(0, 0, 4, 4)])).is_some());
+    }
+
+    #[test]
+    fn lighting_change() {
+        let config = config();
+        let mut scene = learned(&config);
+
+        // the whole frame changing is the lights going on, it becomes the background
+        assert_eq!(update(&mut scene, &config, &frame(200, &[])), None);
+        assert_eq!(update(&mut scene, &config, &frame(200, &[])), None);
+        assert!(update(&mut scene, &config, &frame(100, &[(0, 0, 2, 2)])).is_none());
+    }
+
+    #[test]
+    fn min_area() {
+        let config = config().with_min_area(0.05);
+        let mut scene = learned(&config);
+
+        // 1 of 192 cells is under 5%, 16 are over
+        assert_eq!(
+            update(&mut scene, &config, &frame(50, &[(0, 0, 1, 1)])),
+            None
+        );
+        let boxes = update(&mut scene, &config, &frame(50, &[(8, 4, 4, 4)])).unwrap();
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].x, 0.5);
+    }
+
+    #[test]
+    fn largest_first() {
+        let config = config();
+        let mut scene = learned(&config);
+
+        let boxes = update(
+            &mut scene,
+            &config,
+            &frame(50, &[(0, 0, 1, 1), (8, 6, 3, 3)]),
+        )
+        .unwrap();
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].area, 9.0 / 192.0);
+        assert_eq!(boxes[1].area, 1.0 / 192.0);
+    }
+
+    #[test]
+    fn zones() {
+        let config = config().zone(Zone::new("door", 0.0, 0.0, 0.5, 1.0));
+        let mut scene = learned(&config);
+
+        // outside the door
+        assert_eq!(
+            scene.update(&config, &frame(50, &[(12, 4, 2, 2)]), WIDTH, HEIGHT),
+            None
+        );
+
+        // across the edge only the part inside counts
+        let (boxes, zones) = scene
+            .update(&config, &frame(50, &[(6, 4, 4, 2)]), WIDTH, HEIGHT)
+            .unwrap();
+        assert_eq!(zones, ["door"]);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!((boxes[0].x, boxes[0].width), (6.0 / 16.0, 2.0 / 16.0));
+    }
+
+    #[test]
+    fn new_frame_size_starts_over() {
+        let config = config();
+        let mut scene = learned(&config);
+
+        // a square frame makes a 16 x 16 grid
+        let square = vec![50; WIDTH * WIDTH];
+        assert_eq!(scene.update(&config, &square, WIDTH, WIDTH), None);
+        assert_eq!(
+            update(&mut scene, &config, &frame(50, &[(0, 0, 4, 4)])),
+            None
+        );
+    }
+
+    #[test]
+    fn reset() {
+        let config = config();
+        let mut scene = learned(&config);
+
+        scene.reset();
+        assert_eq!(
+            update(&mut scene, &config, &frame(50, &[(0, 0, 4, 4)])),
+            None
+        );
+    }
+
+    #[test]
+    fn short_frame() {
+        let config = config();
+        let mut scene = learned(&config);
+        assert_eq!(scene.update(&config, &[0; 10], WIDTH, HEIGHT), None);
+        assert_eq!(scene.update(&config, &[], 0, 0), None);
+    }
+
+    #[test]
+    fn blobs_touch_diagonally() {
+        // 4 x 3 grid
+        #[rustfmt::skip]
+        let changed = [
+            true,  false, false, true,
+            false, true,  false, false,
+            false, false, false, true,
+        ];
+        let blobs = blobs(&changed, &[true; 12], 4, 3);
+
+        let cells: Vec<Vec<usize>> = blobs
+            .into_iter()
+            .map(|blob| {
+                let mut cells = blob.cells;
+                cells.sort();
+                cells
+            })
+            .collect();
+        assert_eq!(cells, [vec![0, 5], vec![3], vec![11]]);
+    }
+
+    #[test]
+    fn blobs_skip_unwatched() {
+        let changed = [true, true, true];
+        let watched = [true, false, true];
+        assert_eq!(blobs(&changed, &watched, 3, 1).len(), 2);
+    }
+}
@@ -0,0 +1,618 @@
+use esp_idf_sys::EspError;
+use serde::{Deserialize, Serialize};
+
+use crate::convert::{luma, ByteOrder};
+use crate::espcam::{
+    edit_jpeg, DateTime, Frame, FrameMeta, HeapBuffer, JpegError, OwnedFrame, PixelFormat,
+};
+
+mod font;
+
+use font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+// blank column after each glyph and blank row under each line, in font dots
+const GLYPH_SPACING: u32 = 1;
+const ADVANCE: u32 = GLYPH_WIDTH + GLYPH_SPACING;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + GLYPH_SPACING;
+// automatic scaling fits about this many lines of text in the frame height
+const AUTO_SCALE_LINES: u32 = 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OverlayError {
+    #[error("{0} frames can't be drawn on")]
+    UnsupportedFormat(PixelFormat),
+    #[error("frame buffer holds {actual} bytes, {expected} expected")]
+    BufferTooShort { expected: usize, actual: usize },
+    #[error("jpeg decoding or encoding failed: {0}")]
+    Jpeg(#[from] JpegError),
+    #[error("could not allocate the output: {0}")]
+    Alloc(#[from] EspError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+    pub const WHITE: Self = Self::rgb(255, 255, 255);
+    pub const RED: Self = Self::rgb(255, 0, 0);
+    pub const GREEN: Self = Self::rgb(0, 255, 0);
+    pub const YELLOW: Self = Self::rgb(255, 255, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    fn to_rgb565(self) -> u16 {
+        ((self.r as u16 & 0xF8) << 8) | ((self.g as u16 & 0xFC) << 3) | (self.b as u16 >> 3)
+    }
+}
+
+/// A rectangle in frame pixels, clipped to the frame when drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Where a text box goes, the corners keep [`TextStyle::margin`] from the edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    /// Top left corner of the box, in frame pixels
+    At {
+        x: u32,
+        y: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextStyle {
+    pub color: Color,
+    /// Box filled behind the text, `None` draws straight on the image
+    pub background: Option<Color>,
+    /// Pixels per font dot, `None` scales with the frame height
+    pub scale: Option<u32>,
+    /// Around the text inside the box, in font dots
+    pub padding: u32,
+    /// Between the box and the frame edges, in pixels
+    pub margin: u32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            background: Some(Color::BLACK),
+            scale: None,
+            padding: 1,
+            margin: 8,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn scale_for(&self, frame_height: usize) -> u32 {
+        self.scale
+            .unwrap_or(frame_height as u32 / (AUTO_SCALE_LINES * LINE_HEIGHT))
+            .max(1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Element {
+    Text {
+        text: String,
+        position: Position,
+        style: TextStyle,
+    },
+    /// Capture time on the wall clock once it's synced, time since boot before
+    Timestamp {
+        position: Position,
+        style: TextStyle,
+    },
+    /// Outline drawn inside the rectangle
+    Box {
+        rect: Rect,
+        color: Color,
+        thickness: u32,
+    },
+}
+
+/// Text and boxes stamped on frames, drawn in the order they were added.
+///
+/// ```ignore
+/// let overlay = Overlay::cctv("garage").with_utc_offset(3600);
+/// let stamped = overlay.apply(&framebuffer, framebuffer.meta())?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overlay {
+    elements: Vec<Element>,
+    utc_offset: i32,
+    jpeg_quality: u8,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            elements: Vec::new(),
+            utc_offset: 0,
+            jpeg_quality: 80,
+        }
+    }
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture time in the top left corner and `name` in the bottom right
+    pub fn cctv(name: impl Into<String>) -> Self {
+        Self::new()
+            .timestamp(Position::TopLeft, TextStyle::default())
+            .text(name, Position::BottomRight, TextStyle::default())
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn text(mut self, text: impl Into<String>, position: Position, style: TextStyle) -> Self {
+        self.elements.push(Element::Text {
+            text: text.into(),
+            position,
+            style,
+        });
+        self
+    }
+
+    pub fn timestamp(mut self, position: Position, style: TextStyle) -> Self {
+        self.elements.push(Element::Timestamp { position, style });
+        self
+    }
+
+    pub fn rect(mut self, rect: Rect, color: Color, thickness: u32) -> Self {
+        self.elements.push(Element::Box {
+            rect,
+            color,
+            thickness,
+        });
+        self
+    }
+
+    /// Seconds east of UTC the timestamp is shown in
+    pub fn with_utc_offset(mut self, utc_offset: i32) -> Self {
+        self.utc_offset = utc_offset;
+        self
+    }
+
+    /// Quality JPEG frames are encoded with again, from 1 to 100 like [`encode_jpeg`]
+    pub fn with_jpeg_quality(mut self, quality: u8) -> Self {
+        self.jpeg_quality = quality;
+        self
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Draws every element in place
+    pub fn draw(&self, canvas: &mut Canvas, meta: &FrameMeta) {
+        for element in &self.elements {
+            match element {
+                Element::Text {
+                    text,
+                    position,
+                    style,
+                } => {
+                    canvas.draw_text_at(*position, text, style);
+                }
+                Element::Timestamp { position, style } => {
+                    let text = timestamp_text(meta, self.utc_offset);
+                    canvas.draw_text_at(*position, &text, style);
+                }
+                Element::Box {
+                    rect,
+                    color,
+                    thickness,
+                } => canvas.draw_rect(*rect, *color, *thickness),
+            }
+        }
+    }
+
+    /// A copy of the frame with the overlay drawn on it, in the same format.
+    ///
+    /// JPEG frames are decoded and encoded again, which needs 3 bytes per pixel
+    /// in a single block and takes a while on large frames.
+    pub fn apply(&self, frame: &impl Frame, meta: &FrameMeta) -> Result<OwnedFrame, OverlayError> {
+        let (width, height) = (frame.width(), frame.height());
+
+        match frame.format() {
+            PixelFormat::Jpeg => {
+                let jpeg = edit_jpeg(frame.data(), self.jpeg_quality, |data, width, height| {
+                    let mut canvas = Canvas::new(data, width, height, PixelFormat::Rgb888)?;
+                    self.draw(&mut canvas, meta);
+                    Ok::<_, OverlayError>(())
+                })?;
+
+                Ok(OwnedFrame::new(
+                    jpeg,
+                    width,
+                    height,
+                    PixelFormat::Jpeg,
+                    *meta,
+                ))
+            }
+            format => {
+                let mut data = HeapBuffer::copy_from(frame.data())?;
+                let mut canvas = Canvas::new(&mut data, width, height, format)?;
+                self.draw(&mut canvas, meta);
+
+                Ok(OwnedFrame::new(data, width, height, format, *meta))
+            }
+        }
+    }
+}
+
+/// Drawing primitives over a raw RGB565, GRAYSCALE or RGB888 buffer
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    order: ByteOrder,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(
+        data: &'a mut [u8],
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> Result<Self, OverlayError> {
+        if !matches!(
+            format,
+            PixelFormat::Rgb565 | PixelFormat::Grayscale | PixelFormat::Rgb888
+        ) {
+            return Err(OverlayError::UnsupportedFormat(format));
+        }
+
+        let expected = format
+            .frame_len(width, height)
+            .ok_or(OverlayError::UnsupportedFormat(format))?;
+        if data.len() < expected {
+            return Err(OverlayError::BufferTooShort {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        Ok(Self {
+            data,
+            width,
+            height,
+            format,
+            order: ByteOrder::default(),
+        })
+    }
+
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let x_end = (rect.x.saturating_add(rect.width) as usize).min(self.width);
+        let y_end = (rect.y.saturating_add(rect.height) as usize).min(self.height);
+
+        for y in rect.y as usize..y_end {
+            for x in rect.x as usize..x_end {
+                self.put(x, y, color);
+            }
+        }
+    }
+
+    /// Outline `thickness` pixels wide, inside the rectangle
+    pub fn draw_rect(&mut self, rect: Rect, color: Color, thickness: u32) {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = rect;
+        let horizontal = thickness.max(1).min(height);
+        let vertical = thickness.max(1).min(width);
+        // past u32::MAX is off the frame anyway, fill_rect clips it
+        let bottom = y.saturating_add(height - horizontal);
+        let right = x.saturating_add(width - vertical);
+
+        self.fill_rect(Rect::new(x, y, width, horizontal), color);
+        self.fill_rect(Rect::new(x, bottom, width, horizontal), color);
+        self.fill_rect(Rect::new(x, y, vertical, height), color);
+        self.fill_rect(Rect::new(right, y, vertical, height), color);
+    }
+
+    /// Draws `text` in a box with its top left corner at `x`, `y` and returns
+    /// the box. Lines are split on `\n`.
+    pub fn draw_text(&mut self, x: u32, y: u32, text: &str, style: &TextStyle) -> Rect {
+        let scale = style.scale_for(self.height);
+        let (width, height) = text_size(text, scale, style.padding);
+        let rect = Rect::new(x, y, width, height);
+
+        if let Some(background) = style.background {
+            self.fill_rect(rect, background);
+        }
+
+        // like the box, anything past u32::MAX is off the frame
+        let padding = style.padding.saturating_mul(scale);
+        for (row, line) in text.lines().enumerate() {
+            let line_y = y
+                .saturating_add(padding)
+                .saturating_add(dots(row, LINE_HEIGHT, scale));
+            for (column, c) in line.chars().enumerate() {
+                let glyph_x = x
+                    .saturating_add(padding)
+                    .saturating_add(dots(column, ADVANCE, scale));
+                self.draw_glyph(glyph_x, line_y, c, scale, style.color);
+            }
+        }
+
+        rect
+    }
+
+    pub fn draw_text_at(&mut self, position: Position, text: &str, style: &TextStyle) -> Rect {
+        let scale = style.scale_for(self.height);
+        let (width, height) = text_size(text, scale, style.padding);
+        let (frame_width, frame_height) = (self.width as u32, self.height as u32);
+        let right = frame_width.saturating_sub(width.saturating_add(style.margin));
+        let bottom = frame_height.saturating_sub(height.saturating_add(style.margin));
+
+        let (x, y) = match position {
+            Position::TopLeft => (style.margin, style.margin),
+            Position::TopRight => (right, style.margin),
+            Position::BottomLeft => (style.margin, bottom),
+            Position::BottomRight => (right, bottom),
+            Position::At { x, y } => (x, y),
+        };
+        self.draw_text(x, y, text, style)
+    }
+
+    fn draw_glyph(&mut self, x: u32, y: u32, c: char, scale: u32, color: Color) {
+        for (column, bits) in glyph(c).iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits >> row & 1 == 1 {
+                    let dot = Rect::new(
+                        x.saturating_add(dots(column, 1, scale)),
+                        y.saturating_add(row.saturating_mul(scale)),
+                        scale,
+                        scale,
+                    );
+                    self.fill_rect(dot, color);
+                }
+            }
+        }
+    }
+
+    fn put(&mut self, x: usize, y: usize, color: Color) {
+        let i = y * self.width + x;
+
+        match self.format {
+            PixelFormat::Grayscale => self.data[i] = luma(color.r, color.g, color.b),
+            PixelFormat::Rgb565 => {
+                let raw = color.to_rgb565();
+                let bytes = if self.order.rgb565_big_endian {
+                    raw.to_be_bytes()
+                } else {
+                    raw.to_le_bytes()
+                };
+                self.data[i * 2..i * 2 + 2].copy_from_slice(&bytes);
+            }
+            _ => {
+                let pixel = if self.order.rgb888_bgr {
+                    [color.b, color.g, color.r]
+                } else {
+                    [color.r, color.g, color.b]
+                };
+                self.data[i * 3..i * 3 + 3].copy_from_slice(&pixel);
+            }
+        }
+    }
+}
+
+/// Size of the box [`Canvas::draw_text`] draws for `text`, in pixels, at most
+/// `u32::MAX` each way
+pub fn text_size(text: &str, scale: u32, padding: u32) -> (u32, u32) {
+    let columns = text.lines().map(|line| line.chars().count()).max();
+    let rows = text.lines().count();
+
+    let width = dots(columns.unwrap_or(0), ADVANCE, 1).saturating_sub(GLYPH_SPACING);
+    let height = dots(rows, LINE_HEIGHT, 1).saturating_sub(GLYPH_SPACING);
+    let padding = padding.saturating_mul(2);
+    (
+        width.saturating_add(padding).saturating_mul(scale),
+        height.saturating_add(padding).saturating_mul(scale),
+    )
+}
+
+// `count` cells of `size` font dots, in pixels
+fn dots(count: usize, size: u32, scale: u32) -> u32 {
+    u32::try_from(count)
+        .unwrap_or(u32::MAX)
+        .saturating_mul(size)
+        .saturating_mul(scale)
+}
+
+fn timestamp_text(meta: &FrameMeta, utc_offset: i32) -> String {
+    match meta.wall_time() {
+        Some(time) => DateTime::local(time, utc_offset).to_string(),
+        None => {
+            let secs = meta.timestamp.as_secs();
+            format!(
+                "up {:02}:{:02}:{:02}",
+                secs / 3600,
+                secs % 3600 / 60,
+                secs % 60
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(width: usize, height: usize) -> Vec<u8> {
+        vec![0; width * height]
+    }
+
+    #[test]
+    fn draw_rect_outline() {
+        let mut data = gray(6, 5);
+        let mut canvas = Canvas::new(&mut data, 6, 5, PixelFormat::Grayscale).unwrap();
+        canvas.draw_rect(Rect::new(1, 1, 4, 3), Color::WHITE, 1);
+
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0,   0,   0,   0,   0,   0,
+            0, 255, 255, 255, 255,   0,
+            0, 255,   0,   0, 255,   0,
+            0, 255, 255, 255, 255,   0,
+            0,   0,   0,   0,   0,   0,
+        ]);
+    }
+
+    #[test]
+    fn draw_rect_clipped() {
+        let mut data = gray(4, 4);
+        let mut canvas = Canvas::new(&mut data, 4, 4, PixelFormat::Grayscale).unwrap();
+        // reaches past u32::MAX, only the top and left edges are on the frame
+        canvas.draw_rect(Rect::new(2, 2, u32::MAX, u32::MAX), Color::WHITE, 1);
+        canvas.draw_rect(Rect::new(u32::MAX, 0, 10, 10), Color::WHITE, 3);
+
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0, 0,   0,   0,
+            0, 0,   0,   0,
+            0, 0, 255, 255,
+            0, 0, 255,   0,
+        ]);
+    }
+
+    #[test]
+    fn draw_text_off_frame() {
+        let mut data = gray(8, 8);
+        let mut canvas = Canvas::new(&mut data, 8, 8, PixelFormat::Grayscale).unwrap();
+        let style = TextStyle {
+            scale: Some(2),
+            ..Default::default()
+        };
+        canvas.draw_text(u32::MAX - 1, u32::MAX - 1, "hi", &style);
+        assert!(data.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn draw_text_glyph() {
+        let mut data = gray(7, 9);
+        let mut canvas = Canvas::new(&mut data, 7, 9, PixelFormat::Grayscale).unwrap();
+        let style = TextStyle {
+            background: None,
+            scale: Some(1),
+            ..Default::default()
+        };
+        let rect = canvas.draw_text(0, 0, "1", &style);
+
+        assert_eq!(rect, Rect::new(0, 0, 7, 9));
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0, 0,   0,   0,   0, 0, 0,
+            0, 0,   0, 255,   0, 0, 0,
+            0, 0, 255, 255,   0, 0, 0,
+            0, 0,   0, 255,   0, 0, 0,
+            0, 0,   0, 255,   0, 0, 0,
+            0, 0,   0, 255,   0, 0, 0,
+            0, 0,   0, 255,   0, 0, 0,
+            0, 0, 255, 255, 255, 0, 0,
+            0, 0,   0,   0,   0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn draw_text_scaled() {
+        let mut data = gray(4, 4);
+        let mut canvas = Canvas::new(&mut data, 4, 4, PixelFormat::Grayscale).unwrap();
+        let style = TextStyle {
+            background: None,
+            scale: Some(2),
+            padding: 0,
+            ..Default::default()
+        };
+        // the top of the '1', each dot 2x2
+        canvas.draw_text(0, 0, "1", &style);
+
+        #[rustfmt::skip]
+        assert_eq!(data, [
+            0,   0,   0,   0,
+            0,   0,   0,   0,
+            0,   0, 255, 255,
+            0,   0, 255, 255,
+        ]);
+    }
+
+    #[test]
+    fn huge_scale_and_padding() {
+        assert_eq!(text_size("hi", u32::MAX, 1), (u32::MAX, u32::MAX));
+        assert_eq!(text_size("hi", 1, u32::MAX), (u32::MAX, u32::MAX));
+        assert_eq!(text_size("", 2, 1), (4, 4));
+
+        let mut data = gray(8, 8);
+        let mut canvas = Canvas::new(&mut data, 8, 8, PixelFormat::Grayscale).unwrap();
+        let style = TextStyle {
+            color: Color::BLACK,
+            background: Some(Color::WHITE),
+            scale: Some(u32::MAX),
+            padding: u32::MAX,
+            margin: u32::MAX,
+        };
+        // only the background reaches the frame
+        let rect = canvas.draw_text_at(Position::BottomRight, "hi\nthere", &style);
+        assert_eq!(rect, Rect::new(0, 0, u32::MAX, u32::MAX));
+        assert!(data.iter().all(|&pixel| pixel == 255));
+    }
+}
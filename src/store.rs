@@ -0,0 +1,56 @@
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("NVS error: {0}")]
+    Nvs(#[from] EspError),
+    #[error("stored value doesn't parse: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Settings kept as JSON in an NVS namespace, so they survive reboots.
+///
+/// Keys are limited to 15 characters by NVS.
+///
+/// ```ignore
+/// let mut store = Store::new(EspDefaultNvsPartition::take()?, "espcam")?;
+/// camera.set_privacy_mask(store.load("privacy")?.unwrap_or_default())?;
+/// store.save("privacy", &camera.privacy_mask())?;
+/// ```
+pub struct Store {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Store {
+    pub fn new(partition: EspDefaultNvsPartition, namespace: &str) -> Result<Self, StoreError> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, namespace, true)?,
+        })
+    }
+
+    /// `None` when nothing was saved under `key`
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, StoreError> {
+        let Some(len) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+
+        let mut buf = vec![0; len];
+        match self.nvs.get_blob(key, &mut buf)? {
+            Some(json) => Ok(Some(serde_json::from_slice(json)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), StoreError> {
+        let json = serde_json::to_vec(value)?;
+        self.nvs.set_blob(key, &json)?;
+        Ok(())
+    }
+
+    /// Returns whether there was something to remove
+    pub fn remove(&mut self, key: &str) -> Result<bool, StoreError> {
+        Ok(self.nvs.remove(key)?)
+    }
+}
@@ -0,0 +1,11 @@
+mod plan;
+
+#[cfg(target_os = "espidf")]
+mod scheduler;
+
+pub use plan::{CapturePlan, Days, PlanError, PlanProgress, TimeOfDay};
+
+#[cfg(target_os = "espidf")]
+pub use scheduler::{
+    Capture, FileSink, HttpSink, Sink, SinkError, TimeLapse, TimeLapseError, TimeLapseTask,
+};
@@ -0,0 +1,545 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::espcam::{DateTime, FrameSize, Weekday, JPEG_QUALITY_RANGE};
+
+// plan names end up in file paths, keep them short enough for any filesystem
+const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PlanError {
+    #[error("invalid time of day {0:?}, expected HH:MM")]
+    InvalidTime(String),
+    #[error("the capture interval must be at least a second")]
+    ZeroInterval,
+    #[error("jpeg quality {0} is out of range 0..=63")]
+    InvalidQuality(i32),
+    #[error(
+        "invalid plan name {0:?}, expected 1 to 32 bytes, no path separators or control characters"
+    )]
+    InvalidName(String),
+}
+
+/// Set of days of the week a plan captures on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "Vec<Weekday>", into = "Vec<Weekday>")]
+pub struct Days(u8);
+
+impl Days {
+    pub const NONE: Days = Days(0);
+    pub const WEEKDAYS: Days = Days(0b0011111);
+    pub const WEEKEND: Days = Days(0b1100000);
+    pub const ALL: Days = Days(0b1111111);
+
+    pub fn with(self, day: Weekday) -> Self {
+        Days(self.0 | 1 << day as u8)
+    }
+
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.0 & (1 << day as u8) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Default for Days {
+    fn default() -> Self {
+        Days::ALL
+    }
+}
+
+impl FromIterator<Weekday> for Days {
+    fn from_iter<I: IntoIterator<Item = Weekday>>(days: I) -> Self {
+        days.into_iter().fold(Days::NONE, Days::with)
+    }
+}
+
+impl From<Vec<Weekday>> for Days {
+    fn from(days: Vec<Weekday>) -> Self {
+        days.into_iter().collect()
+    }
+}
+
+impl From<Days> for Vec<Weekday> {
+    fn from(days: Days) -> Self {
+        Weekday::ALL
+            .into_iter()
+            .filter(|&day| days.contains(day))
+            .collect()
+    }
+}
+
+/// Local time of day, minute resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub const MIDNIGHT: TimeOfDay = TimeOfDay { hour: 0, minute: 0 };
+
+    pub fn new(hour: u8, minute: u8) -> Self {
+        Self { hour, minute }
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        self.hour as u32 * 60 + self.minute as u32
+    }
+
+    fn validate(&self) -> Result<(), PlanError> {
+        if self.hour > 23 || self.minute > 59 {
+            return Err(PlanError::InvalidTime(format!(
+                "{:02}:{:02}",
+                self.hour, self.minute
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `HH:MM`, 24 hour clock
+impl FromStr for TimeOfDay {
+    type Err = PlanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PlanError::InvalidTime(s.to_string());
+
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour: u8 = hour.parse().map_err(|_| invalid())?;
+        let minute: u8 = minute.parse().map_err(|_| invalid())?;
+        let time = Self::new(hour, minute);
+        time.validate().map_err(|_| invalid())?;
+        Ok(time)
+    }
+}
+
+/// One series of captures: how often, when, and with which settings.
+///
+/// Captures are aligned to multiples of the interval in local time, so a plan
+/// every 15 minutes captures at :00, :15, :30 and :45 however long each
+/// capture takes, and a reboot doesn't shift the series.
+///
+/// ```ignore
+/// let plan = CapturePlan::new("site", Duration::from_secs(600))
+///     .with_window(TimeOfDay::new(7, 0), TimeOfDay::new(19, 0))
+///     .with_days(Days::WEEKDAYS)
+///     .with_frame_size(FrameSize::Uxga)
+///     .with_quality(8)
+///     .with_profile("daylight");
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedPlan")]
+pub struct CapturePlan {
+    pub(super) name: String,
+    interval_secs: u64,
+    start: Option<TimeOfDay>,
+    end: Option<TimeOfDay>,
+    days: Days,
+    active_from: Option<u64>,
+    active_until: Option<u64>,
+    pub(super) frame_size: Option<FrameSize>,
+    pub(super) quality: Option<i32>,
+    pub(super) profile: Option<String>,
+}
+
+// what's stored, checked by validate before it becomes a plan
+#[derive(Deserialize)]
+struct UncheckedPlan {
+    name: String,
+    interval_secs: u64,
+    #[serde(default)]
+    start: Option<TimeOfDay>,
+    #[serde(default)]
+    end: Option<TimeOfDay>,
+    #[serde(default)]
+    days: Days,
+    #[serde(default)]
+    active_from: Option<u64>,
+    #[serde(default)]
+    active_until: Option<u64>,
+    #[serde(default)]
+    frame_size: Option<FrameSize>,
+    #[serde(default)]
+    quality: Option<i32>,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+impl TryFrom<UncheckedPlan> for CapturePlan {
+    type Error = PlanError;
+
+    fn try_from(plan: UncheckedPlan) -> Result<Self, Self::Error> {
+        let plan = CapturePlan {
+            name: plan.name,
+            interval_secs: plan.interval_secs,
+            start: plan.start,
+            end: plan.end,
+            days: plan.days,
+            active_from: plan.active_from,
+            active_until: plan.active_until,
+            frame_size: plan.frame_size,
+            quality: plan.quality,
+            profile: plan.profile,
+        };
+        plan.validate()?;
+        Ok(plan)
+    }
+}
+
+impl CapturePlan {
+    /// Names are used for file names and storage, keep them short and plain
+    pub fn new(name: impl Into<String>, interval: Duration) -> Self {
+        Self {
+            name: name.into(),
+            interval_secs: interval.as_secs().max(1),
+            start: None,
+            end: None,
+            days: Days::ALL,
+            active_from: None,
+            active_until: None,
+            frame_size: None,
+            quality: None,
+            profile: None,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Only captures from `start` until `end`, local time. Wraps past
+    /// midnight when `end` is before `start`.
+    pub fn with_window(mut self, start: TimeOfDay, end: TimeOfDay) -> Self {
+        self.start = Some(start);
+        self.end = Some(end);
+        self
+    }
+
+    pub fn with_days(mut self, days: Days) -> Self {
+        self.days = days;
+        self
+    }
+
+    /// Limits the plan to a date range, e.g. the length of a project
+    pub fn with_active(mut self, from: Option<SystemTime>, until: Option<SystemTime>) -> Self {
+        self.active_from = from.map(unix_secs);
+        self.active_until = until.map(unix_secs);
+        self
+    }
+
+    /// Switched to for the capture only, at most the frame size the camera
+    /// was configured with
+    pub fn with_frame_size(mut self, frame_size: FrameSize) -> Self {
+        self.frame_size = Some(frame_size);
+        self
+    }
+
+    /// Sensor JPEG quality for the capture only, 0 to 63, lower is better.
+    /// Checked by [`validate`](Self::validate).
+    pub fn with_quality(mut self, quality: i32) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Name of a profile in the scheduler's [`ProfileRegistry`], applied for
+    /// the capture only
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn window(&self) -> Option<(TimeOfDay, TimeOfDay)> {
+        match (self.start, self.end) {
+            (None, None) => None,
+            (start, end) => Some((
+                start.unwrap_or(TimeOfDay::MIDNIGHT),
+                end.unwrap_or(TimeOfDay::MIDNIGHT),
+            )),
+        }
+    }
+
+    pub fn days(&self) -> Days {
+        self.days
+    }
+
+    /// Checks the interval, quality and window, plans loaded from JSON are
+    /// checked already
+    pub fn validate(&self) -> Result<(), PlanError> {
+        // sinks use the name as a directory and in headers
+        let name = &self.name;
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || name == "."
+            || name == ".."
+            || name.contains(|c: char| matches!(c, '/' | '\\') || c.is_control())
+        {
+            return Err(PlanError::InvalidName(name.clone()));
+        }
+        if self.interval_secs == 0 {
+            return Err(PlanError::ZeroInterval);
+        }
+        if let Some(quality) = self.quality {
+            if !u8::try_from(quality).is_ok_and(|quality| JPEG_QUALITY_RANGE.contains(&quality)) {
+                return Err(PlanError::InvalidQuality(quality));
+            }
+        }
+        for time in [self.start, self.end].into_iter().flatten() {
+            time.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Whether a capture is due at `now`, given when the last one was taken.
+    /// `utc_offset` is in seconds east of UTC.
+    pub fn is_due(&self, now: SystemTime, utc_offset: i32, last: Option<SystemTime>) -> bool {
+        let now_secs = unix_secs(now);
+        if self.active_from.is_some_and(|from| now_secs < from)
+            || self.active_until.is_some_and(|until| now_secs >= until)
+        {
+            return false;
+        }
+
+        let local = DateTime::local(now, utc_offset);
+        if !self.days.contains(local.weekday()) || !self.in_window(local.minute_of_day()) {
+            return false;
+        }
+
+        let slot = |secs: u64| (secs as i64 + utc_offset as i64) / self.interval_secs as i64;
+        last.is_none_or(|last| slot(unix_secs(last)) < slot(now_secs))
+    }
+
+    fn in_window(&self, minute: u32) -> bool {
+        let Some((start, end)) = self.window() else {
+            return true;
+        };
+        let (start, end) = (start.minute_of_day(), end.minute_of_day());
+
+        if start < end {
+            (start..end).contains(&minute)
+        } else {
+            // overnight, or the whole day when start and end are equal
+            minute >= start || minute < end
+        }
+    }
+}
+
+/// How far a plan has got, kept across reboots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanProgress {
+    pub(super) last_capture: Option<u64>,
+    pub(super) captures: u32,
+}
+
+impl PlanProgress {
+    pub fn last_capture(&self) -> Option<SystemTime> {
+        self.last_capture
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Frames delivered so far, also the index of the next one
+    pub fn captures(&self) -> u32 {
+        self.captures
+    }
+}
+
+pub(super) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01 00:00:00 UTC, a Monday
+    const MONDAY: u64 = 1_704_067_200;
+    const HOUR: u64 = 3600;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn every(secs: u64) -> CapturePlan {
+        CapturePlan::new("test", Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn days() {
+        assert!(Days::default().contains(Weekday::Sunday));
+        assert!(Days::WEEKDAYS.contains(Weekday::Friday));
+        assert!(!Days::WEEKDAYS.contains(Weekday::Saturday));
+        assert!(Days::WEEKEND.contains(Weekday::Saturday));
+        assert!(Days::NONE.is_empty());
+
+        let days = Days::NONE.with(Weekday::Monday).with(Weekday::Sunday);
+        assert!(days.contains(Weekday::Monday));
+        assert!(!days.contains(Weekday::Tuesday));
+        assert_eq!(Weekday::ALL.into_iter().collect::<Days>(), Days::ALL);
+    }
+
+    #[test]
+    fn days_json() {
+        let json = serde_json::to_string(&Days::WEEKEND).unwrap();
+        assert_eq!(json, r#"["Saturday","Sunday"]"#);
+        assert_eq!(serde_json::from_str::<Days>(&json).unwrap(), Days::WEEKEND);
+        assert_eq!(serde_json::from_str::<Days>("[]").unwrap(), Days::NONE);
+    }
+
+    #[test]
+    fn time_of_day() {
+        assert_eq!("07:30".parse(), Ok(TimeOfDay::new(7, 30)));
+        assert_eq!("0:00".parse(), Ok(TimeOfDay::MIDNIGHT));
+        assert_eq!("23:59".parse(), Ok(TimeOfDay::new(23, 59)));
+        for invalid in ["24:00", "12:60", "7", "ab:cd", "-1:00", ""] {
+            assert_eq!(
+                invalid.parse::<TimeOfDay>(),
+                Err(PlanError::InvalidTime(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn in_window() {
+        assert!(every(60).in_window(0));
+
+        let day = every(60).with_window(TimeOfDay::new(7, 0), TimeOfDay::new(19, 0));
+        assert!(!day.in_window(7 * 60 - 1));
+        assert!(day.in_window(7 * 60));
+        assert!(day.in_window(19 * 60 - 1));
+        assert!(!day.in_window(19 * 60));
+
+        let night = every(60).with_window(TimeOfDay::new(22, 0), TimeOfDay::new(6, 0));
+        assert!(night.in_window(23 * 60));
+        assert!(night.in_window(0));
+        assert!(night.in_window(5 * 60 + 59));
+        assert!(!night.in_window(6 * 60));
+        assert!(!night.in_window(12 * 60));
+
+        let all_day = every(60).with_window(TimeOfDay::new(8, 0), TimeOfDay::new(8, 0));
+        assert!((0..24 * 60).all(|minute| all_day.in_window(minute)));
+    }
+
+    #[test]
+    fn due_once_per_slot() {
+        let plan = every(600);
+        assert!(plan.is_due(at(MONDAY + 605), 0, None));
+        // same ten minutes
+        assert!(!plan.is_due(at(MONDAY + 605), 0, Some(at(MONDAY + 600))));
+        // a slow capture in the previous slot doesn't push the next one back
+        assert!(plan.is_due(at(MONDAY + 600), 0, Some(at(MONDAY + 599))));
+    }
+
+    #[test]
+    fn due_in_local_time() {
+        // hourly on the local hour, half an hour off UTC
+        let plan = every(HOUR);
+        let (last, now) = (at(MONDAY + 1799), at(MONDAY + 1800));
+        assert!(plan.is_due(now, 1800, Some(last)));
+        assert!(!plan.is_due(now, 0, Some(last)));
+    }
+
+    #[test]
+    fn due_on_days() {
+        let weekend = every(60).with_days(Days::WEEKEND);
+        assert!(!weekend.is_due(at(MONDAY), 0, None));
+        // still Sunday an hour west of UTC
+        assert!(weekend.is_due(at(MONDAY), -3600, None));
+    }
+
+    #[test]
+    fn due_in_window() {
+        let plan = every(60).with_window(TimeOfDay::new(7, 0), TimeOfDay::new(19, 0));
+        assert!(plan.is_due(at(MONDAY + 8 * HOUR), 0, None));
+        assert!(!plan.is_due(at(MONDAY + 8 * HOUR), -2 * 3600, None));
+        assert!(!plan.is_due(at(MONDAY + 20 * HOUR), 0, None));
+    }
+
+    #[test]
+    fn due_while_active() {
+        let plan = every(60).with_active(Some(at(MONDAY + HOUR)), Some(at(MONDAY + 2 * HOUR)));
+        assert!(!plan.is_due(at(MONDAY), 0, None));
+        assert!(plan.is_due(at(MONDAY + HOUR), 0, None));
+        assert!(!plan.is_due(at(MONDAY + 2 * HOUR), 0, None));
+    }
+
+    #[test]
+    fn validate() {
+        // the builder never makes a zero interval
+        assert_eq!(every(0).interval(), Duration::from_secs(1));
+        assert_eq!(every(60).with_quality(0).validate(), Ok(()));
+        assert_eq!(every(60).with_quality(63).validate(), Ok(()));
+        assert_eq!(
+            every(60).with_quality(64).validate(),
+            Err(PlanError::InvalidQuality(64))
+        );
+        assert_eq!(
+            every(60).with_quality(-1).validate(),
+            Err(PlanError::InvalidQuality(-1))
+        );
+        assert_eq!(
+            every(60)
+                .with_window(TimeOfDay::new(24, 0), TimeOfDay::MIDNIGHT)
+                .validate(),
+            Err(PlanError::InvalidTime("24:00".to_string()))
+        );
+
+        let named = |name: &str| CapturePlan::new(name, Duration::from_secs(60)).validate();
+        assert_eq!(named("front door"), Ok(()));
+        assert_eq!(named(&"a".repeat(MAX_NAME_LEN)), Ok(()));
+        for name in [
+            "",
+            ".",
+            "..",
+            "../etc",
+            "a/b",
+            "a\\b",
+            "a\nb",
+            &"a".repeat(33),
+        ] {
+            assert_eq!(named(name), Err(PlanError::InvalidName(name.to_string())));
+        }
+    }
+
+    #[test]
+    fn from_json() {
+        let plan = CapturePlan::from_json(r#"{"name":"site","interval_secs":600}"#).unwrap();
+        assert_eq!(plan, CapturePlan::new("site", Duration::from_secs(600)));
+        assert_eq!(plan.days(), Days::ALL);
+
+        let plan = every(600)
+            .with_window(TimeOfDay::new(7, 0), TimeOfDay::new(19, 0))
+            .with_days(Days::WEEKDAYS)
+            .with_frame_size(FrameSize::Svga)
+            .with_quality(10)
+            .with_profile("daylight");
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(CapturePlan::from_json(&json).unwrap(), plan);
+    }
+
+    #[test]
+    fn from_json_rejects() {
+        for json in [
+            r#"{"name":"a","interval_secs":0}"#,
+            r#"{"name":"a","interval_secs":60,"quality":64}"#,
+            r#"{"name":"a","interval_secs":60,"start":{"hour":24,"minute":0}}"#,
+            r#"{"name":"a","interval_secs":60,"end":{"hour":7,"minute":60}}"#,
+            r#"{"name":"","interval_secs":60}"#,
+            r#"{"name":"../a","interval_secs":60}"#,
+        ] {
+            assert!(CapturePlan::from_json(json).is_err(), "{json}");
+        }
+    }
+}
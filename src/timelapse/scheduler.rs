@@ -0,0 +1,470 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use embedded_svc::http::{client::Client, Method};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::io::{EspIOError, Write};
+use esp_idf_sys::EspError;
+
+use crate::espcam::{
+    wall_clock_synced, CameraService, DateTime, FrameSize, JpegError, OwnedFrame, PixelFormat,
+    ProfileRegistry, SensorError, SensorProfile, ServiceError,
+};
+use crate::store::{Store, StoreError};
+
+use super::plan::unix_secs;
+use super::{CapturePlan, PlanError, PlanProgress};
+
+// posting a frame over TLS needs more than the capture itself
+const SCHEDULER_STACK_SIZE: usize = 16 * 1024;
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+// quality non-JPEG frames are encoded with before they're delivered
+const SINK_JPEG_QUALITY: u8 = 90;
+
+const PLANS_KEY: &str = "tl_plans";
+const PROGRESS_KEY: &str = "tl_progress";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimeLapseError {
+    #[error("{0} doesn't fit the frame buffers allocated for {1:?}")]
+    FrameSizeTooLarge(FrameSize, FrameSize),
+    #[error("no sensor profile named {0}")]
+    UnknownProfile(String),
+    #[error("invalid plan: {0}")]
+    Plan(#[from] PlanError),
+    #[error("could not save the plans: {0}")]
+    Store(#[from] StoreError),
+    #[error("sensor error: {0}")]
+    Sensor(#[from] SensorError),
+    #[error("camera service error: {0}")]
+    Service(#[from] ServiceError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error("could not write the frame: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("jpeg encoding failed: {0}")]
+    Encode(#[from] JpegError),
+    #[error("could not connect: {0}")]
+    Connect(#[from] EspError),
+    #[error("upload failed: {0}")]
+    Http(#[from] EspIOError),
+    #[error("upload rejected with status {0}")]
+    Status(u16),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// A frame taken for a plan, handed to a [`Sink`]
+pub struct Capture<'a> {
+    pub plan: &'a CapturePlan,
+    pub frame: &'a OwnedFrame,
+    /// Local time of the capture
+    pub time: DateTime,
+    /// Counts up from 0 in every plan
+    pub index: u32,
+}
+
+impl Capture<'_> {
+    /// The frame as JPEG, encoded here if the camera doesn't capture JPEG
+    pub fn jpeg(&self) -> Result<Cow<'_, [u8]>, JpegError> {
+        if self.frame.format() == PixelFormat::Jpeg {
+            Ok(Cow::Borrowed(self.frame.data()))
+        } else {
+            Ok(Cow::Owned(self.frame.to_jpeg(SINK_JPEG_QUALITY)?.to_vec()))
+        }
+    }
+}
+
+/// Where captured frames go. Closures work too:
+///
+/// ```ignore
+/// let sink = |capture: &Capture| {
+///     log::info!("{} #{}: {} bytes", capture.plan.name(), capture.index, capture.frame.data().len());
+///     Ok(())
+/// };
+/// ```
+pub trait Sink: Send {
+    fn deliver(&mut self, capture: &Capture<'_>) -> Result<(), SinkError>;
+}
+
+impl<F> Sink for F
+where
+    F: FnMut(&Capture<'_>) -> Result<(), SinkError> + Send,
+{
+    fn deliver(&mut self, capture: &Capture<'_>) -> Result<(), SinkError> {
+        self(capture)
+    }
+}
+
+/// Writes every frame to `dir/<plan>/<index>.jpg`, e.g. on a mounted SD card
+pub struct FileSink {
+    dir: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Sink for FileSink {
+    fn deliver(&mut self, capture: &Capture<'_>) -> Result<(), SinkError> {
+        let dir = self.dir.join(capture.plan.name());
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(format!("{:06}.jpg", capture.index)),
+            capture.jpeg()?,
+        )?;
+        Ok(())
+    }
+}
+
+/// POSTs every frame as `image/jpeg`, with the plan, index and capture time
+/// in `X-Timelapse-*` headers
+pub struct HttpSink {
+    url: String,
+}
+
+impl HttpSink {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+impl Sink for HttpSink {
+    fn deliver(&mut self, capture: &Capture<'_>) -> Result<(), SinkError> {
+        let jpeg = capture.jpeg()?;
+
+        let configuration = Configuration {
+            timeout: Some(HTTP_TIMEOUT),
+            ..Default::default()
+        };
+        let mut client = Client::wrap(EspHttpConnection::new(&configuration)?);
+
+        let (length, index, time) = (
+            jpeg.len().to_string(),
+            capture.index.to_string(),
+            capture.time.to_string(),
+        );
+        let headers = [
+            ("Content-Type", "image/jpeg"),
+            ("Content-Length", length.as_str()),
+            ("X-Timelapse-Plan", capture.plan.name()),
+            ("X-Timelapse-Index", index.as_str()),
+            ("X-Timelapse-Time", time.as_str()),
+        ];
+
+        let mut request = client.request(Method::Post, &self.url, &headers)?;
+        request.write_all(&jpeg)?;
+        let response = request.submit()?;
+
+        match response.status() {
+            200..=299 => Ok(()),
+            status => Err(SinkError::Status(status)),
+        }
+    }
+}
+
+// shared between the scheduler task and the handle
+struct Schedule {
+    plans: Vec<CapturePlan>,
+    progress: HashMap<String, PlanProgress>,
+    store: Option<Store>,
+}
+
+impl Schedule {
+    fn save_plans(&mut self) -> Result<(), StoreError> {
+        match &mut self.store {
+            Some(store) => store.save(PLANS_KEY, &self.plans),
+            None => Ok(()),
+        }
+    }
+
+    fn save_progress(&mut self) -> Result<(), StoreError> {
+        match &mut self.store {
+            Some(store) => store.save(PROGRESS_KEY, &self.progress),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Takes time-lapse captures on a [`CameraService`] following a set of
+/// [`CapturePlan`]s, and hands them to a [`Sink`].
+///
+/// With a [`Store`] the plans and their progress are saved to NVS and picked
+/// up again after a reboot. Progress is written after every capture, keep
+/// intervals in minutes rather than seconds to spare the flash.
+///
+/// Nothing is captured until the wall clock has been set by SNTP.
+///
+/// ```ignore
+/// let store = Store::new(nvs.clone(), "timelapse")?;
+/// let timelapse = TimeLapse::recover(store)?.with_utc_offset(3600);
+/// let task = timelapse.start(service.clone(), FileSink::new("/sdcard/timelapse"))?;
+/// task.set_plan(CapturePlan::new("site", Duration::from_secs(600)))?;
+/// ```
+pub struct TimeLapse {
+    schedule: Schedule,
+    profiles: ProfileRegistry,
+    utc_offset: i32,
+}
+
+impl TimeLapse {
+    pub fn new(plans: Vec<CapturePlan>) -> Self {
+        Self {
+            schedule: Schedule {
+                plans,
+                progress: HashMap::new(),
+                store: None,
+            },
+            profiles: ProfileRegistry::default(),
+            utc_offset: 0,
+        }
+    }
+
+    /// Picks up the plans and progress saved in `store`, and keeps saving
+    /// there from now on
+    pub fn recover(store: Store) -> Result<Self, StoreError> {
+        let plans = store.load(PLANS_KEY)?.unwrap_or_default();
+        let progress = store.load(PROGRESS_KEY)?.unwrap_or_default();
+
+        Ok(Self {
+            schedule: Schedule {
+                plans,
+                progress,
+                store: Some(store),
+            },
+            profiles: ProfileRegistry::default(),
+            utc_offset: 0,
+        })
+    }
+
+    /// Seconds east of UTC that windows and days are in
+    pub fn with_utc_offset(mut self, utc_offset: i32) -> Self {
+        self.utc_offset = utc_offset;
+        self
+    }
+
+    /// Where plan profiles are looked up, the builtin ones by default
+    pub fn with_profiles(mut self, profiles: ProfileRegistry) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    pub fn start(
+        self,
+        service: Arc<CameraService>,
+        mut sink: impl Sink + 'static,
+    ) -> Result<TimeLapseTask, ServiceError> {
+        let schedule = Arc::new(Mutex::new(self.schedule));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let (task_schedule, task_running) = (schedule.clone(), running.clone());
+        let (profiles, utc_offset) = (self.profiles, self.utc_offset);
+        let task = std::thread::Builder::new()
+            .name("timelapse".into())
+            .stack_size(SCHEDULER_STACK_SIZE)
+            .spawn(move || {
+                while task_running.load(Ordering::SeqCst) {
+                    std::thread::sleep(SCHEDULER_TICK);
+                    if !wall_clock_synced() {
+                        continue;
+                    }
+
+                    let now = SystemTime::now();
+                    let due: Vec<(CapturePlan, PlanProgress)> = {
+                        let schedule = task_schedule.lock().unwrap();
+                        schedule
+                            .plans
+                            .iter()
+                            .map(|plan| {
+                                let progress = schedule
+                                    .progress
+                                    .get(&plan.name)
+                                    .copied()
+                                    .unwrap_or_default();
+                                (plan.clone(), progress)
+                            })
+                            .filter(|(plan, progress)| {
+                                plan.is_due(now, utc_offset, progress.last_capture())
+                            })
+                            .collect()
+                    };
+
+                    for (plan, mut progress) in due {
+                        // a failed capture still uses up its slot, so it isn't
+                        // retried every tick
+                        progress.last_capture = Some(unix_secs(now));
+
+                        match capture(&service, &profiles, &plan) {
+                            Ok(frame) => {
+                                let capture = Capture {
+                                    plan: &plan,
+                                    frame: &frame,
+                                    time: DateTime::local(
+                                        frame.wall_time().unwrap_or(now),
+                                        utc_offset,
+                                    ),
+                                    index: progress.captures,
+                                };
+                                match sink.deliver(&capture) {
+                                    Ok(()) => progress.captures += 1,
+                                    Err(err) => log::warn!(
+                                        "could not deliver {} #{}: {}",
+                                        plan.name,
+                                        progress.captures,
+                                        err
+                                    ),
+                                }
+                            }
+                            Err(err) => log::warn!("capture for {} failed: {}", plan.name, err),
+                        }
+
+                        let mut schedule = task_schedule.lock().unwrap();
+                        // the plan may have been removed while capturing
+                        if schedule.plans.iter().any(|p| p.name == plan.name) {
+                            schedule.progress.insert(plan.name, progress);
+                            if let Err(err) = schedule.save_progress() {
+                                log::warn!("could not save time-lapse progress: {}", err);
+                            }
+                        }
+                    }
+                }
+            })?;
+
+        Ok(TimeLapseTask {
+            schedule,
+            running,
+            task: Some(task),
+        })
+    }
+}
+
+/// A running [`TimeLapse`], stopped when dropped
+pub struct TimeLapseTask {
+    schedule: Arc<Mutex<Schedule>>,
+    running: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl TimeLapseTask {
+    pub fn plans(&self) -> Vec<CapturePlan> {
+        self.schedule.lock().unwrap().plans.clone()
+    }
+
+    pub fn progress(&self, name: &str) -> Option<PlanProgress> {
+        self.schedule.lock().unwrap().progress.get(name).copied()
+    }
+
+    /// Adds the plan, or replaces the one with the same name keeping its
+    /// progress. The plan is checked with [`CapturePlan::validate`] first.
+    pub fn set_plan(&self, plan: CapturePlan) -> Result<(), TimeLapseError> {
+        plan.validate()?;
+
+        let mut schedule = self.schedule.lock().unwrap();
+        match schedule.plans.iter_mut().find(|p| p.name == plan.name) {
+            Some(existing) => *existing = plan,
+            None => schedule.plans.push(plan),
+        }
+        Ok(schedule.save_plans()?)
+    }
+
+    /// Removes the plan and its progress, returns whether there was one
+    pub fn remove_plan(&self, name: &str) -> Result<bool, StoreError> {
+        let mut schedule = self.schedule.lock().unwrap();
+        let before = schedule.plans.len();
+        schedule.plans.retain(|plan| plan.name != name);
+        if schedule.plans.len() == before {
+            return Ok(false);
+        }
+
+        schedule.progress.remove(name);
+        schedule.save_plans()?;
+        schedule.save_progress()?;
+        Ok(true)
+    }
+}
+
+impl Drop for TimeLapseTask {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(task) = self.task.take() {
+            task.join().ok();
+        }
+    }
+}
+
+/// Applies the plan's settings, takes a frame and puts the previous settings
+/// back
+fn capture(
+    service: &CameraService,
+    profiles: &ProfileRegistry,
+    plan: &CapturePlan,
+) -> Result<Arc<OwnedFrame>, TimeLapseError> {
+    let profile: Option<SensorProfile> = match &plan.profile {
+        Some(name) => Some(
+            profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| TimeLapseError::UnknownProfile(name.clone()))?,
+        ),
+        None => None,
+    };
+    if plan.frame_size.is_none() && plan.quality.is_none() && profile.is_none() {
+        return Ok(service.snapshot("timelapse")?);
+    }
+
+    let (frame_size, quality) = (plan.frame_size, plan.quality);
+    let saved = service.configure(move |camera| {
+        let configured = camera.config().frame_size();
+        if let Some(frame_size) = frame_size {
+            let pixels = |size: FrameSize| size.width() * size.height();
+            if pixels(frame_size) > pixels(configured) {
+                return Err(TimeLapseError::FrameSizeTooLarge(frame_size, configured));
+            }
+        }
+
+        let sensor = camera.sensor();
+        let saved = sensor.settings()?;
+        let applied = (|| {
+            if let Some(frame_size) = frame_size {
+                sensor.set_framesize(frame_size)?;
+            }
+            if let Some(quality) = quality {
+                sensor.set_quality(quality)?;
+            }
+            Ok::<_, SensorError>(())
+        })();
+        if let Err(err) = applied {
+            sensor.apply_settings(&saved).ok();
+            return Err(err.into());
+        }
+
+        if let Some(profile) = profile {
+            for (setting, err) in camera.apply_profile(&profile).failed() {
+                log::warn!("could not apply {:?}: {}", setting, err);
+            }
+        }
+        camera.settle(camera.config().settle_time());
+        Ok(saved)
+    })??;
+
+    let frame = service.snapshot("timelapse");
+
+    service.configure(move |camera| {
+        if let Err(err) = camera.sensor().apply_settings(&saved) {
+            log::warn!("could not restore the sensor settings: {}", err);
+        }
+        camera.settle(camera.config().settle_time());
+    })?;
+
+    Ok(frame?)
+}